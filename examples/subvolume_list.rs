@@ -1,11 +1,7 @@
 use libbtrfsutil::IterateSubvolume;
 
 fn main() {
-    for (path, info) in IterateSubvolume::new("/")
-        .iter_with_info()
-        .unwrap()
-        .filter_map(|s| s.ok())
-    {
+    for (path, info) in IterateSubvolume::new("/").iter().filter_map(|s| s.ok()) {
         println!(
             "ID {} gen {} top level {} path {}",
             info.id(),
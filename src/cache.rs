@@ -0,0 +1,70 @@
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use crate::{subvolume_info_with_id, Error, ErrorKind, SubvolumeInfo};
+
+/// A cache of [`subvolume_info_with_id`] results, keyed by the device of the
+/// given path and the subvolume ID.
+///
+/// Fields such as [`uuid`] or [`otime`] never change, but others such as
+/// [`ctransid`] or [`flags`] do, so entries expire after a time-to-live and
+/// should be [`invalidate`]d after mutating a subvolume.
+///
+/// Every Btrfs subvolume has its own device number, so the same subvolume
+/// queried through paths in different subvolumes is cached separately.
+///
+/// [`uuid`]: SubvolumeInfo::uuid
+/// [`otime`]: SubvolumeInfo::otime
+/// [`ctransid`]: SubvolumeInfo::ctransid
+/// [`flags`]: SubvolumeInfo::flags
+/// [`invalidate`]: Self::invalidate
+pub struct SubvolumeInfoCache {
+    ttl: Duration,
+    entries: HashMap<(u64, u64), (Instant, SubvolumeInfo)>,
+}
+
+impl SubvolumeInfoCache {
+    /// Creates an empty cache whose entries expire after `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Gets information about the subvolume with the given `id` on the
+    /// filesystem containing the `path`, fetching it only if there is no fresh
+    /// cached entry.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+    pub fn get<P: AsRef<Path>>(&mut self, path: P, id: u64) -> Result<SubvolumeInfo, Error> {
+        let dev = fs::metadata(&path)
+            .map_err(|e| Error::with_errno(ErrorKind::STAT_FAILED, e.raw_os_error().unwrap_or(0)))?
+            .dev();
+        let key = (dev, id);
+
+        if let Some((fetched, info)) = self.entries.get(&key) {
+            if fetched.elapsed() < self.ttl {
+                return Ok(info.clone());
+            }
+        }
+        let info = subvolume_info_with_id(path, id)?;
+        self.entries.insert(key, (Instant::now(), info.clone()));
+        Ok(info)
+    }
+
+    /// Removes the cached entries of the subvolume with the given `id`.
+    pub fn invalidate(&mut self, id: u64) {
+        self.entries.retain(|_, (_, info)| info.id() != id);
+    }
+
+    /// Removes all cached entries.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
@@ -1,25 +1,83 @@
+#[cfg(not(feature = "static-messages"))]
+use std::str;
 use std::{
     ffi::CStr,
     fmt::{self, Display},
-    io, str,
+    io,
+    path::{Path, PathBuf},
 };
 
+/// An OS error code, the [`source`] of an [`Error`].
+///
+/// [`source`]: std::error::Error::source
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub struct Errno(i32);
 
+/// Formats as the symbolic name, if known, the raw code and the message, e.g.
+/// `ENOENT (2): No such file or directory`.
 impl Display for Errno {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
-        io::Error::from_raw_os_error(self.0).fmt(f)
+        let mut buf = [0 as std::os::raw::c_char; 128];
+        let ret = unsafe { libc::strerror_r(self.0, buf.as_mut_ptr(), buf.len()) };
+        let msg = if ret == 0 {
+            unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy()
+        } else {
+            "Unknown error".into()
+        };
+        match self.name() {
+            Some(name) => write!(f, "{} ({}): {}", name, self.0, msg),
+            None => write!(f, "errno {}: {}", self.0, msg),
+        }
     }
 }
 impl std::error::Error for Errno {}
 
+impl Errno {
+    /// Returns the raw error code, e.g. `libc::ENOENT`.
+    pub fn raw(&self) -> i32 {
+        self.0
+    }
+
+    /// Returns the symbolic name of this errno, e.g. `"ENOENT"`, if it is one
+    /// commonly returned by Btrfs operations.
+    fn name(self) -> Option<&'static str> {
+        let name = match self.0 {
+            libc::EPERM => "EPERM",
+            libc::ENOENT => "ENOENT",
+            libc::EINTR => "EINTR",
+            libc::EIO => "EIO",
+            libc::EAGAIN => "EAGAIN",
+            libc::ENOMEM => "ENOMEM",
+            libc::EACCES => "EACCES",
+            libc::EBUSY => "EBUSY",
+            libc::EEXIST => "EEXIST",
+            libc::EXDEV => "EXDEV",
+            libc::ENOTDIR => "ENOTDIR",
+            libc::EISDIR => "EISDIR",
+            libc::EINVAL => "EINVAL",
+            libc::ENOTTY => "ENOTTY",
+            libc::ENOSPC => "ENOSPC",
+            libc::EROFS => "EROFS",
+            libc::ENAMETOOLONG => "ENAMETOOLONG",
+            libc::ENOTEMPTY => "ENOTEMPTY",
+            libc::ELOOP => "ELOOP",
+            libc::EOPNOTSUPP => "EOPNOTSUPP",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
 impl From<Errno> for io::Error {
     fn from(e: Errno) -> Self {
         io::Error::from_raw_os_error(e.0)
     }
 }
 
+/// Error kinds at and above this value are raised by this crate rather than
+/// by libbtrfsutil.
+const CRATE_ERROR_BASE: ffi::btrfs_util_error::Type = 0x100;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Ord, PartialOrd)]
 pub struct ErrorKind(ffi::btrfs_util_error::Type);
 
@@ -75,6 +133,127 @@ impl ErrorKind {
         ErrorKind(ffi::btrfs_util_error::BTRFS_UTIL_ERROR_INO_LOOKUP_USER_FAILED);
     pub const FS_INFO_FAILED: ErrorKind =
         ErrorKind(ffi::btrfs_util_error::BTRFS_UTIL_ERROR_FS_INFO_FAILED);
+
+    /// The subvolume is protected by a [`ProtectedSet`].
+    ///
+    /// [`ProtectedSet`]: crate::ProtectedSet
+    pub const PROTECTED: ErrorKind = ErrorKind(CRATE_ERROR_BASE);
+    /// The owner of a new subvolume could not be changed.
+    pub const CHOWN_FAILED: ErrorKind = ErrorKind(CRATE_ERROR_BASE + 1);
+    /// The subvolume is not read-only, which is required to send it.
+    pub const NOT_READ_ONLY: ErrorKind = ErrorKind(CRATE_ERROR_BASE + 2);
+    /// The source and destination of a snapshot are on different filesystems.
+    pub const CROSS_FILESYSTEM: ErrorKind = ErrorKind(CRATE_ERROR_BASE + 3);
+    /// Subvolume information could not be serialized.
+    pub const SERIALIZE_FAILED: ErrorKind = ErrorKind(CRATE_ERROR_BASE + 4);
+    /// The operation was cancelled before it completed.
+    pub const CANCELLED: ErrorKind = ErrorKind(CRATE_ERROR_BASE + 5);
+    /// The qgroup limit of a subvolume could not be set.
+    pub const QGROUP_LIMIT_FAILED: ErrorKind = ErrorKind(CRATE_ERROR_BASE + 6);
+    /// The received metadata of a subvolume could not be set.
+    pub const SET_RECEIVED_SUBVOL_FAILED: ErrorKind = ErrorKind(CRATE_ERROR_BASE + 7);
+
+    /// Returns the name of this kind, e.g. `"SUBVOLUME_NOT_FOUND"`, or
+    /// `"UNKNOWN"` for an unknown kind.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ErrorKind::OK => "OK",
+            ErrorKind::STOP_ITERATION => "STOP_ITERATION",
+            ErrorKind::NO_MEMORY => "NO_MEMORY",
+            ErrorKind::INVALID_ARGUMENT => "INVALID_ARGUMENT",
+            ErrorKind::NOT_BTRFS => "NOT_BTRFS",
+            ErrorKind::NOT_SUBVOLUME => "NOT_SUBVOLUME",
+            ErrorKind::SUBVOLUME_NOT_FOUND => "SUBVOLUME_NOT_FOUND",
+            ErrorKind::OPEN_FAILED => "OPEN_FAILED",
+            ErrorKind::RMDIR_FAILED => "RMDIR_FAILED",
+            ErrorKind::UNLINK_FAILED => "UNLINK_FAILED",
+            ErrorKind::STAT_FAILED => "STAT_FAILED",
+            ErrorKind::STATFS_FAILED => "STATFS_FAILED",
+            ErrorKind::SEARCH_FAILED => "SEARCH_FAILED",
+            ErrorKind::INO_LOOKUP_FAILED => "INO_LOOKUP_FAILED",
+            ErrorKind::SUBVOL_GETFLAGS_FAILED => "SUBVOL_GETFLAGS_FAILED",
+            ErrorKind::SUBVOL_SETFLAGS_FAILED => "SUBVOL_SETFLAGS_FAILED",
+            ErrorKind::SUBVOL_CREATE_FAILED => "SUBVOL_CREATE_FAILED",
+            ErrorKind::SNAP_CREATE_FAILED => "SNAP_CREATE_FAILED",
+            ErrorKind::SNAP_DESTROY_FAILED => "SNAP_DESTROY_FAILED",
+            ErrorKind::DEFAULT_SUBVOL_FAILED => "DEFAULT_SUBVOL_FAILED",
+            ErrorKind::SYNC_FAILED => "SYNC_FAILED",
+            ErrorKind::START_SYNC_FAILED => "START_SYNC_FAILED",
+            ErrorKind::WAIT_SYNC_FAILED => "WAIT_SYNC_FAILED",
+            ErrorKind::GET_SUBVOL_INFO_FAILED => "GET_SUBVOL_INFO_FAILED",
+            ErrorKind::GET_SUBVOL_ROOTREF_FAILED => "GET_SUBVOL_ROOTREF_FAILED",
+            ErrorKind::INO_LOOKUP_USER_FAILED => "INO_LOOKUP_USER_FAILED",
+            ErrorKind::FS_INFO_FAILED => "FS_INFO_FAILED",
+            ErrorKind::PROTECTED => "PROTECTED",
+            ErrorKind::CHOWN_FAILED => "CHOWN_FAILED",
+            ErrorKind::NOT_READ_ONLY => "NOT_READ_ONLY",
+            ErrorKind::CROSS_FILESYSTEM => "CROSS_FILESYSTEM",
+            ErrorKind::SERIALIZE_FAILED => "SERIALIZE_FAILED",
+            ErrorKind::CANCELLED => "CANCELLED",
+            ErrorKind::QGROUP_LIMIT_FAILED => "QGROUP_LIMIT_FAILED",
+            ErrorKind::SET_RECEIVED_SUBVOL_FAILED => "SET_RECEIVED_SUBVOL_FAILED",
+            _ => "UNKNOWN",
+        }
+    }
+
+    /// Returns the message for a kind raised by this crate.
+    fn crate_message(self) -> Option<&'static str> {
+        let msg = match self {
+            ErrorKind::PROTECTED => "subvolume is protected",
+            ErrorKind::CHOWN_FAILED => "could not change owner of subvolume",
+            ErrorKind::NOT_READ_ONLY => "subvolume must be read-only to send",
+            ErrorKind::CROSS_FILESYSTEM => "source and destination are on different filesystems",
+            ErrorKind::SERIALIZE_FAILED => "could not serialize subvolume information",
+            ErrorKind::CANCELLED => "operation cancelled",
+            ErrorKind::QGROUP_LIMIT_FAILED => "could not set qgroup limit",
+            ErrorKind::SET_RECEIVED_SUBVOL_FAILED => "could not set received subvolume metadata",
+            _ => return None,
+        };
+        Some(msg)
+    }
+
+    /// Returns the message for this kind from a static table mirroring
+    /// libbtrfsutil's, or [`None`] for an unknown kind.
+    #[cfg(feature = "static-messages")]
+    fn static_message(self) -> Option<&'static str> {
+        let msg = match self {
+            ErrorKind::OK => "success",
+            ErrorKind::STOP_ITERATION => "stop iteration",
+            ErrorKind::NO_MEMORY => "cannot allocate memory",
+            ErrorKind::INVALID_ARGUMENT => "invalid argument",
+            ErrorKind::NOT_BTRFS => "not a Btrfs filesystem",
+            ErrorKind::NOT_SUBVOLUME => "not a Btrfs subvolume",
+            ErrorKind::SUBVOLUME_NOT_FOUND => "subvolume not found",
+            ErrorKind::OPEN_FAILED => "could not open",
+            ErrorKind::RMDIR_FAILED => "could not rmdir",
+            ErrorKind::UNLINK_FAILED => "could not unlink",
+            ErrorKind::STAT_FAILED => "could not stat",
+            ErrorKind::STATFS_FAILED => "could not statfs",
+            ErrorKind::SEARCH_FAILED => "could not search B-tree",
+            ErrorKind::INO_LOOKUP_FAILED => "could not lookup inode",
+            ErrorKind::SUBVOL_GETFLAGS_FAILED => "could not get subvolume flags",
+            ErrorKind::SUBVOL_SETFLAGS_FAILED => "could not set subvolume flags",
+            ErrorKind::SUBVOL_CREATE_FAILED => "could not create subvolume",
+            ErrorKind::SNAP_CREATE_FAILED => "could not create snapshot",
+            ErrorKind::SNAP_DESTROY_FAILED => "could not destroy subvolume/snapshot",
+            ErrorKind::DEFAULT_SUBVOL_FAILED => "could not set default subvolume",
+            ErrorKind::SYNC_FAILED => "could not sync filesystem",
+            ErrorKind::START_SYNC_FAILED => "could not start filesystem sync",
+            ErrorKind::WAIT_SYNC_FAILED => "could not wait for filesystem sync",
+            ErrorKind::GET_SUBVOL_INFO_FAILED => {
+                "could not get subvolume information with BTRFS_IOC_GET_SUBVOL_INFO"
+            }
+            ErrorKind::GET_SUBVOL_ROOTREF_FAILED => {
+                "could not get rootref information with BTRFS_IOC_GET_SUBVOL_ROOTREF"
+            }
+            ErrorKind::INO_LOOKUP_USER_FAILED => {
+                "could not resolve subvolume path with BTRFS_IOC_INO_LOOKUP_USER"
+            }
+            ErrorKind::FS_INFO_FAILED => "could not get filesystem information",
+            _ => return None,
+        };
+        Some(msg)
+    }
 }
 
 impl From<ErrorKind> for u32 {
@@ -83,10 +262,26 @@ impl From<ErrorKind> for u32 {
     }
 }
 
+impl TryFrom<u32> for ErrorKind {
+    /// The unknown code.
+    type Error = u32;
+
+    fn try_from(code: u32) -> Result<Self, Self::Error> {
+        let kind = ErrorKind(code as ffi::btrfs_util_error::Type);
+        if kind <= ErrorKind::FS_INFO_FAILED || kind.crate_message().is_some() {
+            Ok(kind)
+        } else {
+            Err(code)
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Error {
     kind: ErrorKind,
     errno: Errno,
+    context: Option<PathBuf>,
+    subvolume_id: Option<u64>,
 }
 
 impl Error {
@@ -97,9 +292,37 @@ impl Error {
         Error {
             kind: ErrorKind(kind),
             errno: Errno(errno),
+            context: None,
+            subvolume_id: None,
         }
     }
 
+    /// Creates an Error from a known errno, for failures outside of libbtrfsutil.
+    #[inline]
+    pub(crate) fn with_errno(kind: ErrorKind, errno: i32) -> Self {
+        Error {
+            kind,
+            errno: Errno(errno),
+            context: None,
+            subvolume_id: None,
+        }
+    }
+
+    /// Attaches the path the failed operation was given, replacing any path
+    /// already attached. This is returned by [`context`](Self::context) and
+    /// included in the [`Display`] output, so callers can annotate an error
+    /// returned from a lower layer.
+    pub fn with_context<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.context = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Attaches the ID of the subvolume the failed operation was given.
+    pub(crate) fn with_subvolume_id(mut self, id: u64) -> Self {
+        self.subvolume_id = Some(id);
+        self
+    }
+
     /// Returns the corresponding [`ErrorKind`] for this error.
     pub fn kind(&self) -> ErrorKind {
         self.kind
@@ -110,14 +333,69 @@ impl Error {
         self.errno.0
     }
 
+    /// Returns the symbolic name of the underlying errno, e.g. `"ENOENT"`, or
+    /// [`None`] if it is not one commonly returned by Btrfs operations.
+    pub fn errno_name(&self) -> Option<&'static str> {
+        self.errno.name()
+    }
+
+    /// Returns the path the failed operation was given, if known.
+    pub fn context(&self) -> Option<&Path> {
+        self.context.as_deref()
+    }
+
+    /// Returns the ID of the subvolume the failed operation was given, if
+    /// known.
+    pub fn subvolume_id(&self) -> Option<u64> {
+        self.subvolume_id
+    }
+
     /// Returns the corresponding [`io::Error`] for the underlying errno.
     pub fn os_error(&self) -> io::Error {
         io::Error::from_raw_os_error(self.errno.0)
     }
+
+    /// Returns whether retrying the operation might succeed.
+    ///
+    /// Errors of kind [`INVALID_ARGUMENT`], [`NOT_BTRFS`], [`NOT_SUBVOLUME`],
+    /// [`SUBVOLUME_NOT_FOUND`] and [`PROTECTED`] are never transient. Other
+    /// errors are transient if their kind is [`NO_MEMORY`] or their errno is
+    /// one of `EINTR`, `EAGAIN`, `EBUSY`, `ENOMEM` or `ENOSPC`.
+    ///
+    /// [`INVALID_ARGUMENT`]: ErrorKind::INVALID_ARGUMENT
+    /// [`NOT_BTRFS`]: ErrorKind::NOT_BTRFS
+    /// [`NOT_SUBVOLUME`]: ErrorKind::NOT_SUBVOLUME
+    /// [`SUBVOLUME_NOT_FOUND`]: ErrorKind::SUBVOLUME_NOT_FOUND
+    /// [`PROTECTED`]: ErrorKind::PROTECTED
+    /// [`NO_MEMORY`]: ErrorKind::NO_MEMORY
+    pub fn is_transient(&self) -> bool {
+        match self.kind {
+            ErrorKind::INVALID_ARGUMENT
+            | ErrorKind::NOT_BTRFS
+            | ErrorKind::NOT_SUBVOLUME
+            | ErrorKind::SUBVOLUME_NOT_FOUND
+            | ErrorKind::PROTECTED => false,
+            ErrorKind::NO_MEMORY => true,
+            _ => matches!(
+                self.errno.0,
+                libc::EINTR | libc::EAGAIN | libc::EBUSY | libc::ENOMEM | libc::ENOSPC
+            ),
+        }
+    }
+
+    /// Returns whether the operation failed because the filesystem is mounted
+    /// read-only (`EROFS`), as opposed to e.g. lacking permission (`EPERM`).
+    ///
+    /// Creating, deleting and changing subvolumes all fail this way on a
+    /// read-only mount, whatever their error kind.
+    pub fn is_read_only_fs(&self) -> bool {
+        self.errno.0 == libc::EROFS
+    }
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Error {
+    #[cfg(not(feature = "static-messages"))]
+    fn fmt_libbtrfsutil(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str_ptr = unsafe { ffi::btrfs_util_strerror(self.kind.0) };
         if str_ptr.is_null() {
             write!(f, "unknown libbtrfsutil error {}", self.kind.0)
@@ -128,6 +406,43 @@ impl Display for Error {
             write!(f, "{}{}", first_char, &slice[1..])
         }
     }
+
+    #[cfg(feature = "static-messages")]
+    fn fmt_libbtrfsutil(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind.static_message() {
+            Some(msg) => f.write_str(msg),
+            None => write!(f, "unknown libbtrfsutil error {}", self.kind.0),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind.crate_message() {
+            Some(msg) => f.write_str(msg)?,
+            None => self.fmt_libbtrfsutil(f)?,
+        }
+        match (&self.context, self.subvolume_id) {
+            (Some(path), Some(id)) => write!(f, " (subvolume {} on {})", id, path.display()),
+            (Some(path), None) => write!(f, " ({})", path.display()),
+            (None, Some(id)) => write!(f, " (subvolume {})", id),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Error {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Error", 4)?;
+        state.serialize_field("kind", self.kind.as_str())?;
+        state.serialize_field("code", &u32::from(self.kind))?;
+        state.serialize_field("errno", &self.errno.0)?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
 }
 
 impl std::error::Error for Error {
@@ -138,11 +453,14 @@ impl std::error::Error for Error {
 
 #[cfg(test)]
 mod tests {
+    #[cfg(not(feature = "static-messages"))]
     use std::ffi::CStr;
 
-    use crate::Error;
+    use super::Errno;
+    use crate::{Error, ErrorKind};
 
     #[test]
+    #[cfg(not(feature = "static-messages"))]
     fn test_display() {
         unsafe {
             let err = Error::new(4);
@@ -161,4 +479,135 @@ mod tests {
         let received = err.to_string();
         assert_eq!(received, "unknown libbtrfsutil error 99");
     }
+
+    #[test]
+    #[cfg(feature = "static-messages")]
+    fn test_display_static() {
+        let err = Error::with_errno(ErrorKind::NOT_SUBVOLUME, 0);
+        assert_eq!(err.to_string(), "not a Btrfs subvolume");
+    }
+
+    #[test]
+    fn test_kind_round_trip() {
+        let kinds = [
+            ErrorKind::OK,
+            ErrorKind::STOP_ITERATION,
+            ErrorKind::NO_MEMORY,
+            ErrorKind::INVALID_ARGUMENT,
+            ErrorKind::NOT_BTRFS,
+            ErrorKind::NOT_SUBVOLUME,
+            ErrorKind::SUBVOLUME_NOT_FOUND,
+            ErrorKind::OPEN_FAILED,
+            ErrorKind::RMDIR_FAILED,
+            ErrorKind::UNLINK_FAILED,
+            ErrorKind::STAT_FAILED,
+            ErrorKind::STATFS_FAILED,
+            ErrorKind::SEARCH_FAILED,
+            ErrorKind::INO_LOOKUP_FAILED,
+            ErrorKind::SUBVOL_GETFLAGS_FAILED,
+            ErrorKind::SUBVOL_SETFLAGS_FAILED,
+            ErrorKind::SUBVOL_CREATE_FAILED,
+            ErrorKind::SNAP_CREATE_FAILED,
+            ErrorKind::SNAP_DESTROY_FAILED,
+            ErrorKind::DEFAULT_SUBVOL_FAILED,
+            ErrorKind::SYNC_FAILED,
+            ErrorKind::START_SYNC_FAILED,
+            ErrorKind::WAIT_SYNC_FAILED,
+            ErrorKind::GET_SUBVOL_INFO_FAILED,
+            ErrorKind::GET_SUBVOL_ROOTREF_FAILED,
+            ErrorKind::INO_LOOKUP_USER_FAILED,
+            ErrorKind::FS_INFO_FAILED,
+            ErrorKind::PROTECTED,
+            ErrorKind::CHOWN_FAILED,
+            ErrorKind::NOT_READ_ONLY,
+            ErrorKind::CROSS_FILESYSTEM,
+            ErrorKind::SERIALIZE_FAILED,
+            ErrorKind::CANCELLED,
+            ErrorKind::QGROUP_LIMIT_FAILED,
+            ErrorKind::SET_RECEIVED_SUBVOL_FAILED,
+        ];
+        for kind in kinds {
+            assert_eq!(ErrorKind::try_from(u32::from(kind)), Ok(kind));
+        }
+        assert_eq!(ErrorKind::try_from(99), Err(99));
+    }
+
+    #[test]
+    fn test_kind_as_str() {
+        assert_eq!(
+            ErrorKind::SUBVOLUME_NOT_FOUND.as_str(),
+            "SUBVOLUME_NOT_FOUND"
+        );
+        assert_eq!(
+            ErrorKind::try_from(6).unwrap().as_str(),
+            "SUBVOLUME_NOT_FOUND"
+        );
+        assert_eq!(Error::new(99).kind().as_str(), "UNKNOWN");
+    }
+
+    #[test]
+    fn test_errno_name() {
+        let err = Error::with_errno(ErrorKind::STAT_FAILED, libc::ENOENT);
+        assert_eq!(err.errno_name(), Some("ENOENT"));
+        let err = Error::with_errno(ErrorKind::SNAP_DESTROY_FAILED, libc::EROFS);
+        assert_eq!(err.errno_name(), Some("EROFS"));
+        let err = Error::with_errno(ErrorKind::STAT_FAILED, 4095);
+        assert_eq!(err.errno_name(), None);
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let err = Error::with_errno(ErrorKind::SNAP_CREATE_FAILED, libc::ENOSPC);
+        assert!(err.is_transient());
+        let err = Error::with_errno(ErrorKind::SNAP_DESTROY_FAILED, libc::EBUSY);
+        assert!(err.is_transient());
+        let err = Error::with_errno(ErrorKind::NO_MEMORY, 0);
+        assert!(err.is_transient());
+        let err = Error::with_errno(ErrorKind::SNAP_DESTROY_FAILED, libc::EPERM);
+        assert!(!err.is_transient());
+        let err = Error::with_errno(ErrorKind::NOT_SUBVOLUME, libc::EINTR);
+        assert!(!err.is_transient());
+        let err = Error::with_errno(ErrorKind::PROTECTED, libc::EPERM);
+        assert!(!err.is_transient());
+    }
+
+    #[test]
+    fn test_is_read_only_fs() {
+        let err = Error::with_errno(ErrorKind::SUBVOL_CREATE_FAILED, libc::EROFS);
+        assert!(err.is_read_only_fs());
+        assert!(!err.is_transient());
+        let err = Error::with_errno(ErrorKind::SNAP_DESTROY_FAILED, libc::EPERM);
+        assert!(!err.is_read_only_fs());
+    }
+
+    #[test]
+    fn test_display_context() {
+        let err = Error::with_errno(ErrorKind::PROTECTED, libc::EPERM);
+        let msg = err.to_string();
+        let err = err.with_subvolume_id(257);
+        assert_eq!(err.subvolume_id(), Some(257));
+        assert_eq!(err.to_string(), format!("{} (subvolume 257)", msg));
+        let err = err.with_context("/mnt");
+        assert_eq!(err.context(), Some(std::path::Path::new("/mnt")));
+        assert_eq!(err.to_string(), format!("{} (subvolume 257 on /mnt)", msg));
+        let err = err.with_context("/mnt/subvol");
+        assert_eq!(err.context(), Some(std::path::Path::new("/mnt/subvol")));
+    }
+
+    #[test]
+    fn test_errno_display() {
+        assert_eq!(
+            Errno(libc::ENOENT).to_string(),
+            "ENOENT (2): No such file or directory"
+        );
+        assert!(Errno(4095).to_string().starts_with("errno 4095: "));
+    }
+
+    #[test]
+    fn test_source() {
+        let err = Error::with_errno(ErrorKind::STAT_FAILED, 2);
+        let source = std::error::Error::source(&err).unwrap();
+        assert_eq!(source.downcast_ref::<Errno>(), Some(&Errno(2)));
+        assert_eq!(source.downcast_ref::<Errno>().unwrap().raw(), 2);
+    }
 }
@@ -0,0 +1,118 @@
+use std::{path::PathBuf, vec};
+
+use uuid::Uuid;
+
+use crate::{Error, SubvolumeInfo, SubvolumeSource};
+
+/// An in-memory [`SubvolumeSource`] yielding the given subvolumes, for testing
+/// without a Btrfs filesystem.
+#[derive(Debug, Clone, Default)]
+pub struct FakeSubvolumes(Vec<(PathBuf, SubvolumeInfo)>);
+
+impl FakeSubvolumes {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    /// Adds a subvolume, to be yielded after the ones added before.
+    pub fn push<P: Into<PathBuf>>(&mut self, path: P, info: SubvolumeInfo) -> &mut Self {
+        self.0.push((path.into(), info));
+        self
+    }
+}
+
+impl FromIterator<(PathBuf, SubvolumeInfo)> for FakeSubvolumes {
+    fn from_iter<I: IntoIterator<Item = (PathBuf, SubvolumeInfo)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl SubvolumeSource for FakeSubvolumes {
+    type Iter = vec::IntoIter<Result<(PathBuf, SubvolumeInfo), Error>>;
+
+    fn subvolumes(&self) -> Self::Iter {
+        self.0
+            .iter()
+            .cloned()
+            .map(Ok)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+/// Setters to build fake subvolume information.
+impl SubvolumeInfo {
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.0.id = id;
+        self
+    }
+
+    pub fn with_parent_id(mut self, parent_id: u64) -> Self {
+        self.0.parent_id = parent_id;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: u64) -> Self {
+        self.0.flags = flags;
+        self
+    }
+
+    pub fn with_uuid(mut self, uuid: Uuid) -> Self {
+        self.0.uuid = *uuid.as_bytes();
+        self
+    }
+
+    pub fn with_parent_uuid(mut self, parent_uuid: Uuid) -> Self {
+        self.0.parent_uuid = *parent_uuid.as_bytes();
+        self
+    }
+
+    pub fn with_received_uuid(mut self, received_uuid: Uuid) -> Self {
+        self.0.received_uuid = *received_uuid.as_bytes();
+        self
+    }
+
+    pub fn with_generation(mut self, generation: u64) -> Self {
+        self.0.generation = generation;
+        self
+    }
+
+    pub fn with_otransid(mut self, otransid: u64) -> Self {
+        self.0.otransid = otransid;
+        self
+    }
+
+    /// Sets the creation time, in seconds and nanoseconds since Unix Epoch.
+    pub fn with_otime(mut self, sec: i64, nsec: i64) -> Self {
+        self.0.otime.tv_sec = sec;
+        self.0.otime.tv_nsec = nsec;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use uuid::Uuid;
+
+    use crate::{FakeSubvolumes, SubvolumeInfo, SubvolumeSource};
+
+    #[test]
+    fn test_fake_subvolumes() {
+        let source = SubvolumeInfo::new()
+            .with_id(256)
+            .with_uuid(Uuid::from_bytes([1; 16]));
+        let snapshot = SubvolumeInfo::new()
+            .with_id(257)
+            .with_parent_uuid(Uuid::from_bytes([1; 16]));
+        let mut fake = FakeSubvolumes::new();
+        fake.push("a", source).push("b", snapshot);
+
+        let items: Vec<(PathBuf, SubvolumeInfo)> = fake.subvolumes().map(|r| r.unwrap()).collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].0, PathBuf::from("a"));
+        assert_eq!(items[1].1.id(), 257);
+        assert!(items[1].1.is_snapshot_of(&items[0].1));
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs::OpenOptions,
+    io,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    },
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    qgroup, reopen_o_path, retry_on_eintr, Error, ErrorKind, QgroupUsage, Subvolume,
+    SubvolumeIdIterator, SubvolumeInfo, SubvolumeInfoIterator, FS_TREE_OBJECTID,
+};
+
+/// Maximum length of a filesystem label, including the terminating null byte.
+const BTRFS_LABEL_SIZE: usize = 256;
+/// `_IOR(BTRFS_IOCTL_MAGIC, 49, char[BTRFS_LABEL_SIZE])`
+const BTRFS_IOC_GET_FSLABEL: u64 = 0x8100_9431;
+
+/// `_IOR(BTRFS_IOCTL_MAGIC, 31, struct btrfs_ioctl_fs_info_args)`
+const BTRFS_IOC_FS_INFO: u64 = 0x8400_941f;
+/// Size of `struct btrfs_ioctl_fs_info_args`, in which the filesystem UUID
+/// follows the two 64-bit fields `max_id` and `num_devices`.
+const BTRFS_FS_INFO_ARGS_SIZE: usize = 1024;
+
+/// Returns the UUID of the Btrfs filesystem containing the `path`. Unlike
+/// `st_dev`, which differs between subvolumes, it is shared by every
+/// subvolume and mount of a filesystem.
+fn fsid(path: &Path) -> Result<[u8; 16], Error> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .map_err(|e| Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0)))?;
+    let mut args = [0u64; BTRFS_FS_INFO_ARGS_SIZE / 8];
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), BTRFS_IOC_FS_INFO as _, args.as_mut_ptr()) };
+    if ret < 0 {
+        return Err(Error::with_errno(
+            ErrorKind::FS_INFO_FAILED,
+            io::Error::last_os_error().raw_os_error().unwrap_or(0),
+        ));
+    }
+    let mut fsid = [0u8; 16];
+    fsid[..8].copy_from_slice(&args[2].to_ne_bytes());
+    fsid[8..].copy_from_slice(&args[3].to_ne_bytes());
+    Ok(fsid)
+}
+
+/// Returns whether the paths are on the same Btrfs filesystem.
+pub(crate) fn same_filesystem(a: &Path, b: &Path) -> Result<bool, Error> {
+    Ok(fsid(a)? == fsid(b)?)
+}
+
+/// An open handle to a Btrfs filesystem.
+///
+/// The handle holds a file descriptor to a directory in the filesystem, so
+/// operations on it do not need to resolve the path again.
+pub struct Filesystem {
+    fd: OwnedFd,
+}
+
+impl Filesystem {
+    /// Opens the Btrfs filesystem containing the directory at the given `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY)
+            .open(path)
+            .map_err(|e| {
+                Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0))
+            })?;
+        Self::from_fd(file)
+    }
+
+    /// Adopts an already open file descriptor to a directory in a Btrfs
+    /// filesystem, such as one received from another process.
+    ///
+    /// Returns an error of kind [`NOT_BTRFS`](ErrorKind::NOT_BTRFS) if the
+    /// file descriptor does not refer to a Btrfs filesystem.
+    ///
+    /// Every operation on the handle is an ioctl, which the kernel refuses on
+    /// `O_PATH` file descriptors, so such a descriptor is reopened read-only
+    /// through `/proc/self/fd`. This requires procfs to be mounted and read
+    /// permission on the directory, and otherwise fails with
+    /// [`OPEN_FAILED`](ErrorKind::OPEN_FAILED).
+    pub fn from_fd<F: Into<OwnedFd>>(fd: F) -> Result<Self, Error> {
+        let fd = fd.into();
+        let fd = reopen_o_path(fd.as_fd())?.unwrap_or(fd);
+        let errcode = unsafe { ffi::btrfs_util_is_subvolume_fd(fd.as_raw_fd()) };
+        match errcode {
+            ffi::btrfs_util_error::BTRFS_UTIL_OK
+            | ffi::btrfs_util_error::BTRFS_UTIL_ERROR_NOT_SUBVOLUME => Ok(Self { fd }),
+            _ => Err(Error::new(errcode)),
+        }
+    }
+
+    /// Gets information about the subvolume with the given `id`. If `id` is
+    /// zero, the subvolume containing the handle is used.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`) unless `id` is
+    /// zero and the kernel supports `BTRFS_IOC_GET_SUBVOL_INFO` (kernel >= 4.18).
+    pub fn subvolume_info(&self, id: u64) -> Result<SubvolumeInfo, Error> {
+        let mut out = SubvolumeInfo::new();
+        let errcode =
+            unsafe { ffi::btrfs_util_subvolume_info_fd(self.fd.as_raw_fd(), id, out.as_ptr()) };
+        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Err(Error::new(errcode));
+        }
+        Ok(out)
+    }
+
+    /// Opens the subvolume with the given `id`.
+    ///
+    /// The subvolume is opened relative to this handle, by its path relative
+    /// to the subvolume containing the handle, so the handle must be the root
+    /// of that subvolume (e.g. a mount point) and the subvolume must be beneath
+    /// it. Returns an error of kind
+    /// [`SUBVOLUME_NOT_FOUND`](ErrorKind::SUBVOLUME_NOT_FOUND) if there is no
+    /// subvolume with that ID or it cannot be reached from the handle.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+    pub fn subvolume(&self, id: u64) -> Result<Subvolume, Error> {
+        let not_found = || {
+            Error::with_errno(ErrorKind::SUBVOLUME_NOT_FOUND, libc::ENOENT).with_subvolume_id(id)
+        };
+        let path = self.subvolume_path(id)?;
+        let base = self.subvolume_path(0)?;
+        let relative = path.strip_prefix(&base).map_err(|_| not_found())?;
+        let relative = if relative.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            relative
+        };
+
+        let cpath = CString::new(relative.as_os_str().as_bytes()).unwrap();
+        let raw = unsafe {
+            libc::openat(
+                self.fd.as_raw_fd(),
+                cpath.as_ptr(),
+                libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            )
+        };
+        if raw < 0 {
+            return Err(Error::with_errno(
+                ErrorKind::OPEN_FAILED,
+                io::Error::last_os_error().raw_os_error().unwrap_or(0),
+            ));
+        }
+        let subvol = Subvolume::from_fd(unsafe { OwnedFd::from_raw_fd(raw) })?;
+
+        // The path is only right if the handle is the root of its subvolume.
+        let mut opened_id = 0;
+        let errcode =
+            unsafe { ffi::btrfs_util_subvolume_id_fd(subvol.as_raw_fd(), &mut opened_id) };
+        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Err(Error::new(errcode));
+        }
+        if id != 0 && opened_id != id {
+            return Err(not_found());
+        }
+        Ok(subvol)
+    }
+
+    /// Gets the path of the subvolume with the given `id` relative to the
+    /// filesystem root. If `id` is zero, the subvolume containing the handle
+    /// is used.
+    fn subvolume_path(&self, id: u64) -> Result<PathBuf, Error> {
+        let mut ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
+        let errcode =
+            unsafe { ffi::btrfs_util_subvolume_path_fd(self.fd.as_raw_fd(), id, &mut ptr) };
+        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Err(Error::new(errcode).with_subvolume_id(id));
+        }
+        let path = unsafe { CStr::from_ptr(ptr) };
+        let ret = PathBuf::from(OsStr::from_bytes(path.to_bytes()));
+        unsafe { libc::free(ptr as *mut libc::c_void) };
+        Ok(ret)
+    }
+
+    /// Returns the label of this filesystem, which is empty if unset.
+    ///
+    /// Invalid UTF-8 in the label is replaced with `U+FFFD`.
+    pub fn label(&self) -> Result<String, Error> {
+        let mut buf = [0u8; BTRFS_LABEL_SIZE];
+        let ret = unsafe {
+            libc::ioctl(
+                self.fd.as_raw_fd(),
+                BTRFS_IOC_GET_FSLABEL as _,
+                buf.as_mut_ptr(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::with_errno(
+                ErrorKind::FS_INFO_FAILED,
+                io::Error::last_os_error().raw_os_error().unwrap_or(0),
+            ));
+        }
+        let label = CStr::from_bytes_until_nul(&buf)
+            .map_err(|_| Error::with_errno(ErrorKind::FS_INFO_FAILED, libc::EINVAL))?;
+        Ok(label.to_string_lossy().into_owned())
+    }
+
+    /// Forces a sync on this filesystem, like [`sync`](crate::sync) on the
+    /// directory this handle was opened on.
+    ///
+    /// This is retried if interrupted by a signal.
+    pub fn sync(&self) -> Result<(), Error> {
+        retry_on_eintr(|| unsafe { ffi::btrfs_util_sync_fd(self.fd.as_raw_fd()) })
+    }
+
+    /// Returns the IDs of the subvolumes of this filesystem which have been
+    /// deleted but not yet cleaned up. Btrfs cleans up deleted subvolumes in
+    /// the background, so an ID is listed from its deletion until its space
+    /// has been reclaimed.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+    pub fn deleted_subvolumes(&self) -> Result<Vec<u64>, Error> {
+        let mut ptr: *mut u64 = std::ptr::null_mut();
+        let mut len: usize = 0;
+        let errcode = unsafe {
+            ffi::btrfs_util_deleted_subvolumes_fd(self.fd.as_raw_fd(), &mut ptr, &mut len)
+        };
+        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Err(Error::new(errcode));
+        }
+        if ptr.is_null() {
+            return Ok(Vec::new());
+        }
+        let ids = unsafe { std::slice::from_raw_parts(ptr, len) }.to_vec();
+        unsafe { libc::free(ptr as *mut libc::c_void) };
+        Ok(ids)
+    }
+
+    /// Returns the number of subvolumes of this filesystem, not counting the
+    /// root subvolume ([`FS_TREE_OBJECTID`]).
+    ///
+    /// Btrfs does not keep a count, so this enumerates the subvolume IDs,
+    /// which takes a number of ioctls proportional to the number of
+    /// subvolumes, but is cheaper than fetching their information.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+    pub fn subvolume_count(&self) -> Result<usize, Error> {
+        let mut count = 0;
+        for item in self.iter_all_ids()? {
+            item?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns an iterator over the IDs of all subvolumes of this filesystem.
+    /// The returned paths are relative to the filesystem root.
+    fn iter_all_ids(&self) -> Result<SubvolumeIdIterator, Error> {
+        let fd = self.fd.try_clone().map_err(|e| {
+            Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0))
+        })?;
+        SubvolumeIdIterator::with_fd(fd, FS_TREE_OBJECTID, 0)
+    }
+
+    /// Returns an iterator over all subvolumes of this filesystem. The returned
+    /// paths are relative to the filesystem root.
+    fn iter_all(&self) -> Result<SubvolumeInfoIterator, Error> {
+        Ok(self.iter_all_ids()?.into())
+    }
+
+    /// Gets information about the subvolume at the given path relative to the
+    /// filesystem root, such as one returned by [`subvolume_path`], or
+    /// [`None`] if there is no subvolume at that path. An empty path or `/`
+    /// refers to the root subvolume ([`FS_TREE_OBJECTID`]).
+    ///
+    /// The path is matched against the subvolumes of the filesystem rather
+    /// than opened, so this works regardless of which subvolume is mounted.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+    ///
+    /// [`subvolume_path`]: crate::subvolume_path
+    pub fn subvolume_by_path<P: AsRef<Path>>(
+        &self,
+        path: P,
+    ) -> Result<Option<SubvolumeInfo>, Error> {
+        let path: PathBuf = path
+            .as_ref()
+            .components()
+            .filter(|c| !matches!(c, Component::RootDir | Component::CurDir))
+            .collect();
+        if path.as_os_str().is_empty() {
+            return self.subvolume_info(FS_TREE_OBJECTID).map(Some);
+        }
+        for item in self.iter_all()? {
+            let (subvol_path, info) = item?;
+            if subvol_path == path {
+                return Ok(Some(info));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns information about every subvolume of this filesystem, starting
+    /// with the root subvolume ([`FS_TREE_OBJECTID`]), together with the space
+    /// accounted to its level 0 qgroup.
+    ///
+    /// The usage is [`None`] for every subvolume if quotas are disabled, and
+    /// for a subvolume without a qgroup. While a quota rescan is in progress,
+    /// it may not be up to date.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+    pub fn subvolumes_with_usage(
+        &self,
+    ) -> Result<Vec<(SubvolumeInfo, Option<QgroupUsage>)>, Error> {
+        let usage = qgroup::subvolume_usage(self.fd.as_raw_fd())?;
+        let usage_of = |id: u64| usage.as_ref().and_then(|usage| usage.get(&id).copied());
+        let root = self.subvolume_info(FS_TREE_OBJECTID)?;
+        let mut ret = vec![(root, usage_of(FS_TREE_OBJECTID))];
+        for item in self.iter_all()? {
+            let (_, info) = item?;
+            let id = info.id();
+            ret.push((info, usage_of(id)));
+        }
+        Ok(ret)
+    }
+}
+
+impl AsFd for Filesystem {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for Filesystem {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<Filesystem> for OwnedFd {
+    fn from(fs: Filesystem) -> Self {
+        fs.fd
+    }
+}
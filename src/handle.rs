@@ -0,0 +1,266 @@
+use std::{
+    cell::RefCell,
+    fs::OpenOptions,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+        raw::c_int,
+        unix::fs::OpenOptionsExt,
+    },
+    path::Path,
+};
+
+use uuid::Uuid;
+
+use crate::{
+    Error, ErrorKind, ReceivedInfo, SubvolumeIdIterator, SubvolumeInfo, SubvolumeInfoIterator,
+    BTRFS_ROOT_SUBVOL_RDONLY, FS_TREE_OBJECTID,
+};
+
+/// Root item flag set on subvolumes which are being deleted.
+const BTRFS_ROOT_SUBVOL_DEAD: u64 = 1 << 48;
+
+/// Options to open subvolumes
+///
+/// The subvolume is always opened with `O_DIRECTORY`.
+#[derive(Default)]
+pub struct OpenSubvolumeOptions {
+    follow_symlinks: bool,
+    no_atime: bool,
+}
+
+impl OpenSubvolumeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// When false (default), refuse to open the path if its final component is
+    /// a symbolic link (`O_NOFOLLOW`).
+    pub fn follow_symlinks(&mut self, follow_symlinks: bool) -> &mut Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// When true, do not update the access time of the subvolume root
+    /// (`O_NOATIME`). This requires owning the subvolume root or `CAP_FOWNER`.
+    pub fn no_atime(&mut self, no_atime: bool) -> &mut Self {
+        self.no_atime = no_atime;
+        self
+    }
+
+    /// Opens the subvolume at the given `path`.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<Subvolume, Error> {
+        let mut flags: c_int = libc::O_DIRECTORY;
+        if !self.follow_symlinks {
+            flags |= libc::O_NOFOLLOW;
+        }
+        if self.no_atime {
+            flags |= libc::O_NOATIME;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(flags)
+            .open(path)
+            .map_err(|e| {
+                Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0))
+            })?;
+        Subvolume::from_fd(OwnedFd::from(file))
+    }
+}
+
+/// An open handle to a Btrfs subvolume.
+///
+/// The handle holds a file descriptor to the subvolume, so operations on it do
+/// not need to resolve the path again.
+pub struct Subvolume {
+    fd: OwnedFd,
+    info: RefCell<Option<SubvolumeInfo>>,
+}
+
+impl Subvolume {
+    /// Opens the subvolume at the given `path`. See [`OpenSubvolumeOptions`]
+    /// for more options.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        OpenSubvolumeOptions::new().open(path)
+    }
+
+    /// Adopts a file descriptor, checking that it refers to a subvolume root.
+    pub(crate) fn from_fd(fd: OwnedFd) -> Result<Self, Error> {
+        let errcode = unsafe { ffi::btrfs_util_is_subvolume_fd(fd.as_raw_fd()) };
+        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Err(Error::new(errcode));
+        }
+        Ok(Subvolume {
+            fd,
+            info: RefCell::new(None),
+        })
+    }
+
+    /// Gets information about this subvolume and caches it.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`) unless the kernel supports
+    /// `BTRFS_IOC_GET_SUBVOL_INFO` (kernel >= 4.18).
+    pub fn info(&self) -> Result<SubvolumeInfo, Error> {
+        let mut out = SubvolumeInfo::new();
+        let errcode =
+            unsafe { ffi::btrfs_util_subvolume_info_fd(self.fd.as_raw_fd(), 0, out.as_ptr()) };
+        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Err(Error::new(errcode));
+        }
+        self.info.replace(Some(out.clone()));
+        Ok(out)
+    }
+
+    /// Gets information about this subvolume like [`info`], serialized as a
+    /// JSON object.
+    ///
+    /// Returns an error of kind [`SERIALIZE_FAILED`] if serialization fails.
+    ///
+    /// [`info`]: Self::info
+    /// [`SERIALIZE_FAILED`]: ErrorKind::SERIALIZE_FAILED
+    #[cfg(feature = "serde")]
+    pub fn info_json(&self) -> Result<String, Error> {
+        let info = self.info()?;
+        serde_json::to_string(&info)
+            .map_err(|_| Error::with_errno(ErrorKind::SERIALIZE_FAILED, libc::EINVAL))
+    }
+
+    /// Returns the information cached by the last call to [`info`], or [`None`]
+    /// if it has never been fetched.
+    ///
+    /// [`info`]: Self::info
+    pub fn info_cached(&self) -> Option<SubvolumeInfo> {
+        self.info.borrow().clone()
+    }
+
+    /// Calls `f` with the cached information, fetching it first if needed.
+    fn with_info<T>(&self, f: impl FnOnce(&SubvolumeInfo) -> T) -> Result<T, Error> {
+        if self.info.borrow().is_none() {
+            self.info()?;
+        }
+        Ok(f(self.info.borrow().as_ref().unwrap()))
+    }
+
+    /// Returns the ID of this subvolume.
+    ///
+    /// The ID of a subvolume never changes, so this reads the cached
+    /// information, only fetching it if it has never been fetched.
+    pub fn id(&self) -> Result<u64, Error> {
+        self.with_info(SubvolumeInfo::id)
+    }
+
+    /// Returns the UUID of this subvolume.
+    ///
+    /// The UUID of a subvolume never changes, so this reads the cached
+    /// information, only fetching it if it has never been fetched.
+    pub fn uuid(&self) -> Result<Uuid, Error> {
+        self.with_info(SubvolumeInfo::uuid)
+    }
+
+    /// Returns the received metadata of this subvolume, or [`None`] if it was
+    /// not received. See [`SubvolumeInfo::received_info`].
+    ///
+    /// This reads the cached information, only fetching it if it has never
+    /// been fetched, so it may be stale until [`info`] is called again.
+    ///
+    /// [`info`]: Self::info
+    pub fn received_info(&self) -> Result<Option<ReceivedInfo>, Error> {
+        self.with_info(SubvolumeInfo::received_info)
+    }
+
+    /// Returns whether this subvolume still exists, i.e. it has not been
+    /// deleted since it was opened. This neither uses nor updates the cached
+    /// information, and any error is treated as the subvolume not existing.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`) unless the kernel supports
+    /// `BTRFS_IOC_GET_SUBVOL_INFO` (kernel >= 4.18).
+    pub fn exists(&self) -> bool {
+        let mut info = SubvolumeInfo::new();
+        let errcode =
+            unsafe { ffi::btrfs_util_subvolume_info_fd(self.fd.as_raw_fd(), 0, info.as_ptr()) };
+        // A deleted subvolume is unlinked from its parent immediately, but its
+        // root lingers, flagged as dead, until it is cleaned up.
+        errcode == ffi::btrfs_util_error::BTRFS_UTIL_OK
+            && info.flags() & BTRFS_ROOT_SUBVOL_DEAD == 0
+            && (info.id() == FS_TREE_OBJECTID || info.parent_id().is_some())
+    }
+
+    /// Returns whether this subvolume is read-only.
+    pub fn read_only(&self) -> Result<bool, Error> {
+        let mut ret: bool = false;
+        let errcode =
+            unsafe { ffi::btrfs_util_get_subvolume_read_only_fd(self.fd.as_raw_fd(), &mut ret) };
+        if errcode == ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            Ok(ret)
+        } else {
+            Err(Error::new(errcode))
+        }
+    }
+
+    /// Checks that this subvolume can be sent, i.e. it still exists and is
+    /// read-only, before running `btrfs send` on it.
+    ///
+    /// Returns an error of kind [`SUBVOLUME_NOT_FOUND`] if it was deleted, or
+    /// [`NOT_READ_ONLY`] if it is writable.
+    ///
+    /// [`SUBVOLUME_NOT_FOUND`]: ErrorKind::SUBVOLUME_NOT_FOUND
+    /// [`NOT_READ_ONLY`]: ErrorKind::NOT_READ_ONLY
+    pub fn ready_to_send(&self) -> Result<(), Error> {
+        if !self.exists() {
+            return Err(Error::with_errno(
+                ErrorKind::SUBVOLUME_NOT_FOUND,
+                libc::ENOENT,
+            ));
+        }
+        if !self.read_only()? {
+            return Err(Error::with_errno(ErrorKind::NOT_READ_ONLY, libc::EPERM));
+        }
+        Ok(())
+    }
+
+    /// Sets whether this subvolume is read-only, keeping the cached information
+    /// consistent.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+    pub fn set_read_only(&self, read_only: bool) -> Result<(), Error> {
+        let errcode =
+            unsafe { ffi::btrfs_util_set_subvolume_read_only_fd(self.fd.as_raw_fd(), read_only) };
+        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Err(Error::new(errcode));
+        }
+        if let Some(info) = self.info.borrow_mut().as_mut() {
+            if read_only {
+                info.0.flags |= BTRFS_ROOT_SUBVOL_RDONLY;
+            } else {
+                info.0.flags &= !BTRFS_ROOT_SUBVOL_RDONLY;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns an iterator over the subvolumes beneath this subvolume. The
+    /// returned paths are relative to this subvolume.
+    pub fn iter_children(&self) -> Result<SubvolumeInfoIterator, Error> {
+        let fd = self.fd.try_clone().map_err(|e| {
+            Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0))
+        })?;
+        Ok(SubvolumeIdIterator::with_fd(fd, 0, 0)?.into())
+    }
+}
+
+impl AsFd for Subvolume {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for Subvolume {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<Subvolume> for OwnedFd {
+    fn from(subvol: Subvolume) -> Self {
+        subvol.fd
+    }
+}
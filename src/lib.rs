@@ -1,27 +1,131 @@
+mod cache;
 mod error;
+#[cfg(feature = "test-util")]
+mod fake;
+mod filesystem;
+mod handle;
+mod mount;
+mod protect;
 mod qgroup;
+mod retention;
+mod rollback;
 mod subvol;
+mod watch;
 
 use std::{
-    ffi::CString,
-    os::{raw::c_int, unix::prelude::OsStrExt},
-    path::Path,
+    ffi::{CString, OsStr},
+    fs::OpenOptions,
+    io,
+    mem::MaybeUninit,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+        raw::c_int,
+        unix::prelude::{OpenOptionsExt, OsStrExt},
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
-pub use error::{Error, ErrorKind};
-pub use qgroup::QgroupInherit;
+pub use cache::SubvolumeInfoCache;
+pub use error::{Errno, Error, ErrorKind};
+#[cfg(feature = "test-util")]
+pub use fake::FakeSubvolumes;
+pub use filesystem::Filesystem;
+pub use handle::{OpenSubvolumeOptions, Subvolume};
+pub use mount::{
+    is_subvolume_mounted, mountpoint_of, parse_btrfs_mount_options, BtrfsMountOptions,
+};
+pub use protect::ProtectedSet;
+pub use qgroup::{
+    quotas_enabled, set_qgroup_limit, QgroupInherit, QgroupInheritBuilder, QgroupUsage,
+};
+pub use retention::{select_snapshots_by_age, Selection};
+pub use rollback::{prepare_rollback, RollbackPlan};
 pub use subvol::*;
+pub use watch::{watch_subvolumes, SubvolumeEvent};
+
+/// The most commonly used items, for glob importing.
+///
+/// ```no_run
+/// use libbtrfsutil::prelude::*;
+///
+/// # fn main() -> Result<(), Error> {
+/// create_subvolume("/mnt/subvol")?;
+/// CreateSnapshotOptions::new()
+///     .readonly(true)
+///     .create("/mnt/subvol", "/mnt/snapshot")?;
+/// for item in IterateSubvolume::new("/mnt").iter() {
+///     let (path, info) = item?;
+///     println!("{} {}", info.id(), path.display());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub mod prelude {
+    pub use crate::{
+        create_subvolume, delete_subvolume, subvolume_existence, subvolume_id, subvolume_info,
+        subvolume_path, subvolume_read_only, CreateSnapshotOptions, CreateSubvolumeOptions,
+        DeleteSubvolumeOptions, Error, ErrorKind, Existence, IterateSubvolume, Subvolume,
+        SubvolumeInfo,
+    };
+}
+
+/// The raw bindings to libbtrfsutil from the `libbtrfsutil-sys` crate.
+///
+/// This is not covered by the semver guarantees of this crate; the bindings
+/// may change with any release.
+#[cfg(feature = "ffi")]
+pub use ::ffi;
+
 pub const FS_TREE_OBJECTID: u64 = 5;
+/// Root item flag set on read-only subvolumes, see [`SubvolumeInfo::flags`].
+pub const BTRFS_ROOT_SUBVOL_RDONLY: u64 = 1 << 0;
+
+/// Calls `f` until it does not fail with `EINTR`, for blocking operations
+/// which may be interrupted by a signal handler.
+fn retry_on_eintr<F>(mut f: F) -> Result<(), Error>
+where
+    F: FnMut() -> ffi::btrfs_util_error::Type,
+{
+    loop {
+        unsafe { *libc::__errno_location() = 0 };
+        let errcode = f();
+        if errcode == ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Ok(());
+        }
+        let err = Error::new(errcode);
+        if err.errno() != libc::EINTR {
+            return Err(err);
+        }
+    }
+}
+
+/// Fails with `ELOOP` if the final component of the `path` is a symbolic link.
+fn check_not_symlink(path: &Path, kind: ErrorKind) -> Result<(), Error> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            Err(Error::with_errno(kind, libc::ELOOP))
+        }
+        _ => Ok(()),
+    }
+}
 
 /// Forces a sync on a Btrfs filesystem containing the `path`.
+///
+/// This is retried if interrupted by a signal.
 pub fn sync<P: AsRef<Path>>(path: P) -> Result<(), Error> {
     let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
-    let errcode = unsafe { ffi::btrfs_util_sync(cpath.as_ptr()) };
-    if errcode == ffi::btrfs_util_error::BTRFS_UTIL_OK {
-        Ok(())
-    } else {
-        Err(Error::new(errcode))
-    }
+    retry_on_eintr(|| unsafe { ffi::btrfs_util_sync(cpath.as_ptr()) })
+}
+
+/// Waits for the transaction with the given ID on a Btrfs filesystem
+/// containing the `path` to finish. If `transid` is zero, waits for the current
+/// transaction.
+///
+/// This is retried if interrupted by a signal.
+pub fn wait_sync<P: AsRef<Path>>(path: P, transid: u64) -> Result<(), Error> {
+    let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+    retry_on_eintr(|| unsafe { ffi::btrfs_util_wait_sync(cpath.as_ptr(), transid) })
 }
 
 /// Returns whether the given `path` is a Btrfs subvolume.
@@ -36,6 +140,64 @@ pub fn is_subvolume<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
     }
 }
 
+/// Filesystem type of Btrfs in `statfs::f_type`.
+const BTRFS_SUPER_MAGIC: u32 = 0x9123_683e;
+
+/// Returns whether the `path` is on a Btrfs filesystem, with a single
+/// `statfs` call. This is cheaper than letting a Btrfs operation fail with
+/// [`NOT_BTRFS`](ErrorKind::NOT_BTRFS).
+pub fn is_btrfs<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
+    let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+    let mut buf = MaybeUninit::<libc::statfs>::uninit();
+    if unsafe { libc::statfs(cpath.as_ptr(), buf.as_mut_ptr()) } < 0 {
+        return Err(Error::with_errno(
+            ErrorKind::STATFS_FAILED,
+            io::Error::last_os_error().raw_os_error().unwrap_or(0),
+        ));
+    }
+    let buf = unsafe { buf.assume_init() };
+    Ok(buf.f_type as u32 == BTRFS_SUPER_MAGIC)
+}
+
+/// What exists at a path, as returned by [`subvolume_existence`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Existence {
+    /// The path is a Btrfs subvolume.
+    Subvolume,
+    /// The path is on a Btrfs filesystem but is not a subvolume, e.g. a plain
+    /// directory or a file.
+    Directory,
+    /// Nothing exists at the path.
+    Missing,
+    /// The path is not on a Btrfs filesystem.
+    NotBtrfs,
+}
+
+/// Returns what exists at the given `path`.
+///
+/// Unlike [`is_subvolume`], a nonexistent `path` is reported as
+/// [`Existence::Missing`] instead of an error.
+pub fn subvolume_existence<P: AsRef<Path>>(path: P) -> Result<Existence, Error> {
+    let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+    let errcode = unsafe { ffi::btrfs_util_is_subvolume(cpath.as_ptr()) };
+    match errcode {
+        ffi::btrfs_util_error::BTRFS_UTIL_OK => Ok(Existence::Subvolume),
+        ffi::btrfs_util_error::BTRFS_UTIL_ERROR_NOT_SUBVOLUME => Ok(Existence::Directory),
+        ffi::btrfs_util_error::BTRFS_UTIL_ERROR_NOT_BTRFS => Ok(Existence::NotBtrfs),
+        _ => {
+            let err = Error::new(errcode);
+            match err.kind() {
+                ErrorKind::STAT_FAILED | ErrorKind::STATFS_FAILED
+                    if err.errno() == libc::ENOENT =>
+                {
+                    Ok(Existence::Missing)
+                }
+                _ => Err(err),
+            }
+        }
+    }
+}
+
 /// Gets the ID of the subvolume containing the `path`.
 pub fn subvolume_id<P: AsRef<Path>>(path: P) -> Result<u64, Error> {
     let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
@@ -49,21 +211,41 @@ pub fn subvolume_id<P: AsRef<Path>>(path: P) -> Result<u64, Error> {
 }
 
 /// Gets information about the subvolume with the given `id` on the filesystem containing the `path`.
+/// If `id` is zero, the subvolume containing the `path` is used.
 ///
 /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
 pub fn subvolume_info_with_id<P: AsRef<Path>>(path: P, id: u64) -> Result<SubvolumeInfo, Error> {
-    let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
     let mut out = SubvolumeInfo::new();
-    unsafe {
-        let errcode = ffi::btrfs_util_subvolume_info(cpath.as_ptr(), id, out.as_ptr());
-        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
-            return Err(Error::new(errcode));
-        }
-    }
+    subvolume_info_into(path, id, &mut out)?;
     Ok(out)
 }
 
-/// Gets information about the subvolume at the given `path`.
+/// Gets information about the subvolume with the given `id` like
+/// [`subvolume_info_with_id`], but into `out`, so the same [`SubvolumeInfo`]
+/// can be reused in a loop.
+///
+/// `out` is entirely overwritten on success and left untouched on error.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`) unless `id` is zero
+/// and the kernel supports `BTRFS_IOC_GET_SUBVOL_INFO` (kernel >= 4.18).
+pub fn subvolume_info_into<P: AsRef<Path>>(
+    path: P,
+    id: u64,
+    out: &mut SubvolumeInfo,
+) -> Result<(), Error> {
+    let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+    // libbtrfsutil may have filled part of the info when it fails.
+    let mut info = MaybeUninit::<ffi::btrfs_util_subvolume_info>::zeroed();
+    let errcode = unsafe { ffi::btrfs_util_subvolume_info(cpath.as_ptr(), id, info.as_mut_ptr()) };
+    if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+        return Err(Error::new(errcode));
+    }
+    out.0 = unsafe { info.assume_init() };
+    Ok(())
+}
+
+/// Gets information about the subvolume containing the given `path`. The
+/// `path` may be any file or directory in the subvolume, not only its root.
 ///
 /// This requires appropriate privilege (`CAP_SYS_ADMIN`) unless the kernel supports
 /// `BTRFS_IOC_GET_SUBVOL_INFO` (kernel >= 4.18).
@@ -71,6 +253,128 @@ pub fn subvolume_info<P: AsRef<Path>>(path: P) -> Result<SubvolumeInfo, Error> {
     subvolume_info_with_id(path, 0)
 }
 
+/// The identity of a subvolume which can be read without privilege, see
+/// [`subvolume_basic_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BasicInfo {
+    /// The ID of the subvolume.
+    pub id: u64,
+    /// The generation of the subvolume, or [`None`] if it could not be read
+    /// without privilege.
+    pub generation: Option<u64>,
+}
+
+/// Gets the ID and generation of the subvolume containing the `path`.
+///
+/// Unlike [`subvolume_info`], this does not require privilege. The generation
+/// is only available on kernels supporting `BTRFS_IOC_GET_SUBVOL_INFO`
+/// (kernel >= 4.18) or with appropriate privilege (`CAP_SYS_ADMIN`);
+/// otherwise only the ID is returned.
+pub fn subvolume_basic_info<P: AsRef<Path>>(path: P) -> Result<BasicInfo, Error> {
+    match subvolume_info(path.as_ref()) {
+        Ok(info) => Ok(BasicInfo {
+            id: info.id(),
+            generation: Some(info.generation()),
+        }),
+        Err(err) if err.errno() == libc::EPERM => Ok(BasicInfo {
+            id: subvolume_id(path)?,
+            generation: None,
+        }),
+        Err(err) => Err(err),
+    }
+}
+
+/// Returns a descriptor for the same file as `fd` on which ioctls can be
+/// issued: [`None`] if `fd` itself can be used, or `fd` reopened read-only
+/// through `/proc/self/fd` if it was opened with `O_PATH`, on which the kernel
+/// refuses ioctls with `EBADF`.
+///
+/// Reopening requires procfs to be mounted and read permission on the file,
+/// but not search permission on its ancestors.
+fn reopen_o_path(fd: BorrowedFd) -> Result<Option<OwnedFd>, Error> {
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+    if flags < 0 {
+        return Err(Error::with_errno(
+            ErrorKind::OPEN_FAILED,
+            io::Error::last_os_error().raw_os_error().unwrap_or(0),
+        ));
+    }
+    if flags & libc::O_PATH == 0 {
+        return Ok(None);
+    }
+    let file = OpenOptions::new()
+        .read(true)
+        .open(format!("/proc/self/fd/{}", fd.as_raw_fd()))
+        .map_err(|e| Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0)))?;
+    Ok(Some(file.into()))
+}
+
+/// Calls `f` with the raw file descriptor of `fd`, or of its read-only reopening
+/// if it was opened with `O_PATH`, see [`reopen_o_path`].
+fn with_ioctl_fd<T>(fd: BorrowedFd, f: impl FnOnce(c_int) -> Result<T, Error>) -> Result<T, Error> {
+    match reopen_o_path(fd)? {
+        Some(reopened) => f(reopened.as_raw_fd()),
+        None => f(fd.as_raw_fd()),
+    }
+}
+
+/// Returns whether the file descriptor `fd` refers to a Btrfs subvolume root.
+///
+/// This only uses `fstat` and `fstatfs`, so `fd` may be opened with `O_PATH`.
+/// [`subvolume_id_fd`], [`subvolume_info_fd`] and [`subvolume_path_fd`] also
+/// accept `O_PATH`, while the other operations on file descriptors, such as
+/// [`CreateSnapshotOptions::create_fd_at`] and [`Subvolume`] handles, need a
+/// descriptor opened without it.
+pub fn is_subvolume_fd<F: AsFd>(fd: F) -> Result<bool, Error> {
+    let errcode = unsafe { ffi::btrfs_util_is_subvolume_fd(fd.as_fd().as_raw_fd()) };
+    match errcode {
+        ffi::btrfs_util_error::BTRFS_UTIL_OK => Ok(true),
+        ffi::btrfs_util_error::BTRFS_UTIL_ERROR_NOT_SUBVOLUME
+        | ffi::btrfs_util_error::BTRFS_UTIL_ERROR_NOT_BTRFS => Ok(false),
+        _ => Err(Error::new(errcode)),
+    }
+}
+
+/// Gets the ID of the subvolume containing the file descriptor `fd`.
+///
+/// `fd` may be opened with `O_PATH`, in which case it is reopened read-only
+/// through `/proc/self/fd`, as the kernel refuses ioctls on `O_PATH`
+/// descriptors. This requires procfs to be mounted and read permission on
+/// the file, and otherwise fails with
+/// [`OPEN_FAILED`](ErrorKind::OPEN_FAILED), e.g. with `EACCES` or `ENOENT`.
+pub fn subvolume_id_fd<F: AsFd>(fd: F) -> Result<u64, Error> {
+    with_ioctl_fd(fd.as_fd(), |fd| {
+        let mut ret: u64 = 0;
+        let errcode = unsafe { ffi::btrfs_util_subvolume_id_fd(fd, &mut ret) };
+        if errcode == ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            Ok(ret)
+        } else {
+            Err(Error::new(errcode))
+        }
+    })
+}
+
+/// Gets information about the subvolume with the given `id` on the filesystem
+/// containing the file descriptor `fd`. If `id` is zero, the subvolume
+/// containing `fd` is used.
+///
+/// An `O_PATH` descriptor is reopened read-only through `/proc/self/fd`, so
+/// procfs must be mounted and the caller needs read permission on the file,
+/// or this fails with [`OPEN_FAILED`](ErrorKind::OPEN_FAILED).
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`) unless `id` is zero
+/// and the kernel supports `BTRFS_IOC_GET_SUBVOL_INFO` (kernel >= 4.18).
+pub fn subvolume_info_fd<F: AsFd>(fd: F, id: u64) -> Result<SubvolumeInfo, Error> {
+    with_ioctl_fd(fd.as_fd(), |fd| {
+        let mut info = MaybeUninit::<ffi::btrfs_util_subvolume_info>::zeroed();
+        let errcode = unsafe { ffi::btrfs_util_subvolume_info_fd(fd, id, info.as_mut_ptr()) };
+        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Err(Error::new(errcode));
+        }
+        Ok(SubvolumeInfo(unsafe { info.assume_init() }))
+    })
+}
+
 /// Returns whether a subvolume is read-only.
 pub fn subvolume_read_only<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
     let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
@@ -97,61 +401,436 @@ pub fn set_subvolume_read_only<P: AsRef<Path>>(path: P, read_only: bool) -> Resu
     }
 }
 
+/// Set whether a subvolume is read-only and sync the filesystem, so that the
+/// change is committed to disk when this returns. See [`sync`].
+///
+/// This requires appropriate privilege (CAP_SYS_ADMIN).
+pub fn set_subvolume_read_only_durable<P: AsRef<Path>>(
+    path: P,
+    read_only: bool,
+) -> Result<(), Error> {
+    set_subvolume_read_only(path.as_ref(), read_only)?;
+    sync(path)
+}
+
+/// `_IOWR(BTRFS_IOCTL_MAGIC, 37, struct btrfs_ioctl_received_subvol_args)`
+const BTRFS_IOC_SET_RECEIVED_SUBVOL: u64 = 0xc0c8_9425;
+
+/// `struct btrfs_ioctl_timespec`
+#[repr(C)]
+#[derive(Default)]
+struct IoctlTimespec {
+    sec: u64,
+    nsec: u32,
+}
+
+/// `struct btrfs_ioctl_received_subvol_args`
+#[repr(C)]
+#[derive(Default)]
+struct ReceivedSubvolArgs {
+    uuid: [u8; 16],
+    stransid: u64,
+    rtransid: u64,
+    stime: IoctlTimespec,
+    rtime: IoctlTimespec,
+    flags: u64,
+    reserved: [u64; 16],
+}
+
+/// Marks the subvolume at `to_path` as received like the subvolume described
+/// by `from`, by copying its received UUID, `stransid` and `stime`. This is
+/// what `btrfs receive` does after receiving a subvolume, so the subvolume can
+/// then be used as the parent of incremental sends from the same source.
+///
+/// The kernel sets `rtransid` and `rtime` to the current transaction and time
+/// rather than copying them. The subvolume must not be read-only, so it has to
+/// be made read-only afterwards with [`set_subvolume_read_only`] to be sent.
+///
+/// Returns an error of kind [`INVALID_ARGUMENT`](ErrorKind::INVALID_ARGUMENT)
+/// if `from` was not received, and of kind
+/// [`SET_RECEIVED_SUBVOL_FAILED`](ErrorKind::SET_RECEIVED_SUBVOL_FAILED) with
+/// `EROFS` if the subvolume is read-only.
+///
+/// The caller must own the subvolume, unless it has appropriate privilege
+/// (`CAP_FOWNER`).
+pub fn copy_received_metadata<P: AsRef<Path>>(
+    from: &SubvolumeInfo,
+    to_path: P,
+) -> Result<(), Error> {
+    let uuid = from
+        .received_uuid()
+        .ok_or_else(|| Error::with_errno(ErrorKind::INVALID_ARGUMENT, libc::EINVAL))?;
+    let file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY)
+        .open(to_path)
+        .map_err(|e| Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0)))?;
+    let stime = from.as_raw().stime;
+    let mut args = ReceivedSubvolArgs {
+        uuid: *uuid.as_bytes(),
+        stransid: from.as_raw().stransid,
+        stime: IoctlTimespec {
+            sec: stime.tv_sec as u64,
+            nsec: stime.tv_nsec as u32,
+        },
+        ..Default::default()
+    };
+    let ret = unsafe {
+        libc::ioctl(
+            file.as_raw_fd(),
+            BTRFS_IOC_SET_RECEIVED_SUBVOL as _,
+            &mut args,
+        )
+    };
+    if ret < 0 {
+        return Err(Error::with_errno(
+            ErrorKind::SET_RECEIVED_SUBVOL_FAILED,
+            io::Error::last_os_error().raw_os_error().unwrap_or(0),
+        ));
+    }
+    Ok(())
+}
+
+/// Gets the ID of the default subvolume of the filesystem containing the `path`.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn default_subvolume<P: AsRef<Path>>(path: P) -> Result<u64, Error> {
+    let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+    let mut ret: u64 = 0;
+    let errcode = unsafe { ffi::btrfs_util_get_default_subvolume(cpath.as_ptr(), &mut ret) };
+    if errcode == ffi::btrfs_util_error::BTRFS_UTIL_OK {
+        Ok(ret)
+    } else {
+        Err(Error::new(errcode))
+    }
+}
+
+/// Sets the default subvolume of the filesystem containing the `path`. If `id`
+/// is zero, the subvolume containing the `path` is used.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn set_default_subvolume<P: AsRef<Path>>(path: P, id: u64) -> Result<(), Error> {
+    let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
+    let errcode = unsafe { ffi::btrfs_util_set_default_subvolume(cpath.as_ptr(), id) };
+    if errcode == ffi::btrfs_util_error::BTRFS_UTIL_OK {
+        Ok(())
+    } else {
+        Err(Error::new(errcode))
+    }
+}
+
+/// Sets the default subvolume of the filesystem containing the `mount` to the
+/// subvolume at `target`.
+///
+/// Returns an error of kind [`NOT_SUBVOLUME`] if `target` is not a subvolume,
+/// or [`CROSS_FILESYSTEM`] if it is on a different filesystem than `mount`.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+///
+/// [`NOT_SUBVOLUME`]: ErrorKind::NOT_SUBVOLUME
+/// [`CROSS_FILESYSTEM`]: ErrorKind::CROSS_FILESYSTEM
+pub fn set_default_subvolume_to_path<P: AsRef<Path>, Q: AsRef<Path>>(
+    mount: P,
+    target: Q,
+) -> Result<(), Error> {
+    match subvolume_existence(target.as_ref())? {
+        Existence::Subvolume => {}
+        Existence::Directory => {
+            return Err(Error::with_errno(ErrorKind::NOT_SUBVOLUME, libc::EINVAL))
+        }
+        Existence::Missing => return Err(Error::with_errno(ErrorKind::STAT_FAILED, libc::ENOENT)),
+        Existence::NotBtrfs => return Err(Error::with_errno(ErrorKind::NOT_BTRFS, libc::EINVAL)),
+    }
+    if !filesystem::same_filesystem(mount.as_ref(), target.as_ref())? {
+        return Err(Error::with_errno(ErrorKind::CROSS_FILESYSTEM, libc::EXDEV));
+    }
+    set_default_subvolume(mount, subvolume_id(target)?)
+}
+
 /// Options to delete subvolumes
-pub struct DeleteSubvolumeOptions {
+pub struct DeleteSubvolumeOptions<'a> {
     recursive: bool,
+    follow_symlinks: bool,
+    protected: Option<&'a ProtectedSet>,
+    cancel: Option<&'a AtomicBool>,
 }
 
-impl DeleteSubvolumeOptions {
+impl<'a> DeleteSubvolumeOptions<'a> {
     pub fn new() -> Self {
-        Self { recursive: false }
+        Self {
+            recursive: false,
+            follow_symlinks: false,
+            protected: None,
+            cancel: None,
+        }
     }
     /// When true, delete subvolumes beneath the given subvolume before
     /// attempting to delete the given subvolume.
+    ///
+    /// The recursion is done by libbtrfsutil in userspace, deleting the
+    /// subvolumes one by one in post order, so it works on any kernel but is
+    /// not atomic: if a deletion fails, the subvolumes deleted before it stay
+    /// deleted. Unless running with `CAP_SYS_ADMIN`, it requires a kernel
+    /// supporting unprivileged iteration (kernel >= 4.18), see
+    /// [`IterateSubvolume::new`].
     pub fn recursive(&mut self, recursive: bool) -> &mut Self {
         self.recursive = recursive;
         self
     }
 
+    /// When false (default), refuse to delete the given path with `ELOOP` if
+    /// its final component is a symbolic link.
+    pub fn follow_symlinks(&mut self, follow_symlinks: bool) -> &mut Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Refuse to delete the given subvolume with [`ErrorKind::PROTECTED`] if
+    /// it, or any subvolume beneath it when deleting recursively, is in
+    /// `protected`.
+    pub fn respect_protection(&mut self, protected: &'a ProtectedSet) -> &mut Self {
+        self.protected = Some(protected);
+        self
+    }
+
+    /// When deleting recursively, stop before deleting the next subvolume once
+    /// `cancel` is set.
+    ///
+    /// The subvolumes are then deleted one by one in the order of [`plan`],
+    /// rather than by libbtrfsutil, and a cancellation leaves the subtree
+    /// partially deleted. [`delete`] then fails with [`ErrorKind::CANCELLED`],
+    /// carrying the path and ID of the first subvolume which was not deleted
+    /// (see [`Error::context`] and [`Error::subvolume_id`]), while
+    /// [`delete_with_outcome`] returns which subvolumes were deleted and which
+    /// remain.
+    ///
+    /// [`plan`]: Self::plan
+    /// [`delete`]: Self::delete
+    /// [`delete_with_outcome`]: Self::delete_with_outcome
+    pub fn with_cancel(&mut self, cancel: &'a AtomicBool) -> &mut Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
     /// Deletes a subvolume or snapshot.
+    ///
+    /// This is retried if interrupted by a signal.
     pub fn delete<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        match self.delete_with_outcome(path)? {
+            DeleteOutcome::Completed => Ok(()),
+            DeleteOutcome::Cancelled { remaining, .. } => {
+                let (subvol_path, id) = remaining.into_iter().next().unwrap();
+                Err(Error::with_errno(ErrorKind::CANCELLED, libc::ECANCELED)
+                    .with_context(subvol_path)
+                    .with_subvolume_id(id))
+            }
+        }
+    }
+
+    /// Deletes a subvolume or snapshot like [`delete`](Self::delete), but
+    /// returns a cancellation (see [`with_cancel`](Self::with_cancel)) as a
+    /// [`DeleteOutcome`] rather than an error, so the caller can tell which
+    /// subvolumes were deleted before it.
+    pub fn delete_with_outcome<P: AsRef<Path>>(&self, path: P) -> Result<DeleteOutcome, Error> {
+        if let (true, Some(cancel)) = (self.recursive, self.cancel) {
+            return self.delete_cancellable(path.as_ref(), cancel);
+        }
+        if !self.follow_symlinks {
+            check_not_symlink(path.as_ref(), ErrorKind::SNAP_DESTROY_FAILED)?;
+        }
+        if let Some(protected) = self.protected {
+            self.check_protection(protected, path.as_ref())?;
+        }
         let mut flags: c_int = 0;
         if self.recursive {
             flags |= ffi::BTRFS_UTIL_DELETE_SUBVOLUME_RECURSIVE as c_int;
         }
         let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
-        unsafe {
-            let errcode = ffi::btrfs_util_delete_subvolume(cpath.as_ptr(), flags);
-            if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
-                return Err(Error::new(errcode));
+        retry_on_eintr(|| unsafe { ffi::btrfs_util_delete_subvolume(cpath.as_ptr(), flags) })?;
+        Ok(DeleteOutcome::Completed)
+    }
+
+    /// Lists the subvolumes [`delete`] would delete, in the order it would
+    /// delete them, without deleting anything. Fails like [`delete`] if the
+    /// path is a symbolic link or protected.
+    ///
+    /// libbtrfsutil cannot read qgroup usage, so the plan does not estimate
+    /// the space which would be freed.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`) unless the kernel supports
+    /// `BTRFS_IOC_GET_SUBVOL_INFO` (kernel >= 4.18).
+    ///
+    /// [`delete`]: Self::delete
+    pub fn plan<P: AsRef<Path>>(&self, path: P) -> Result<DeletePlan, Error> {
+        let path = path.as_ref();
+        if !self.follow_symlinks {
+            check_not_symlink(path, ErrorKind::SNAP_DESTROY_FAILED)?;
+        }
+        if let Some(protected) = self.protected {
+            self.check_protection(protected, path)?;
+        }
+        let mut subvolumes = Vec::new();
+        if self.recursive {
+            for item in IterateSubvolume::new(path).post_order().iter_with_info()? {
+                let (child, info) = item?;
+                subvolumes.push((path.join(child), info));
+            }
+        }
+        subvolumes.push((path.to_path_buf(), subvolume_info(path)?));
+        Ok(DeletePlan(subvolumes))
+    }
+
+    fn delete_cancellable(&self, path: &Path, cancel: &AtomicBool) -> Result<DeleteOutcome, Error> {
+        let mut plan = self
+            .plan(path)?
+            .0
+            .into_iter()
+            .map(|(subvol_path, info)| (subvol_path, info.id()));
+        let mut deleted = Vec::new();
+        while let Some((subvol_path, id)) = plan.next() {
+            if cancel.load(Ordering::Relaxed) {
+                let mut remaining = vec![(subvol_path, id)];
+                remaining.extend(plan);
+                return Ok(DeleteOutcome::Cancelled { deleted, remaining });
+            }
+            let cpath = CString::new(subvol_path.as_os_str().as_bytes()).unwrap();
+            retry_on_eintr(|| unsafe { ffi::btrfs_util_delete_subvolume(cpath.as_ptr(), 0) })?;
+            deleted.push((subvol_path, id));
+        }
+        Ok(DeleteOutcome::Completed)
+    }
+
+    fn check_protection(&self, protected: &ProtectedSet, path: &Path) -> Result<(), Error> {
+        let protected_error = || Error::with_errno(ErrorKind::PROTECTED, libc::EPERM);
+        if protected.is_protected(subvolume_info(path)?.uuid()) {
+            return Err(protected_error());
+        }
+        if self.recursive {
+            for item in IterateSubvolume::new(path).iter_with_info()? {
+                let (_, info) = item?;
+                if protected.is_protected(info.uuid()) {
+                    return Err(protected_error());
+                }
             }
         }
         Ok(())
     }
 }
 
+/// How a deletion ended, as returned by
+/// [`DeleteSubvolumeOptions::delete_with_outcome`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeleteOutcome {
+    /// Every subvolume was deleted.
+    Completed,
+    /// The deletion was cancelled with [`DeleteSubvolumeOptions::with_cancel`]
+    /// before every subvolume was deleted.
+    Cancelled {
+        /// The paths and IDs of the subvolumes which were deleted, in the
+        /// order they were deleted.
+        deleted: Vec<(PathBuf, u64)>,
+        /// The paths and IDs of the subvolumes which were not deleted, in the
+        /// order they would have been deleted. This is never empty.
+        remaining: Vec<(PathBuf, u64)>,
+    },
+}
+
+/// The subvolumes a deletion would delete, as returned by
+/// [`DeleteSubvolumeOptions::plan`].
+#[derive(Debug, Clone)]
+pub struct DeletePlan(Vec<(PathBuf, SubvolumeInfo)>);
+
+impl DeletePlan {
+    /// Returns the paths and information of the subvolumes, in the order they
+    /// would be deleted.
+    pub fn subvolumes(&self) -> &[(PathBuf, SubvolumeInfo)] {
+        &self.0
+    }
+}
+
 /// Delete a subvolume. See [`DeleteSubvolumeOptions`] for more options.
 pub fn delete_subvolume<P: AsRef<Path>>(path: P) -> Result<(), Error> {
     DeleteSubvolumeOptions::new().delete(path)
 }
 
 /// Options to create subvolumes
+#[derive(Clone)]
 pub struct CreateSubvolumeOptions {
     qgroup: Option<QgroupInherit>,
+    readonly: bool,
+    follow_symlinks: bool,
+    owner: Option<(u32, u32)>,
+    idempotent: bool,
 }
 
 impl CreateSubvolumeOptions {
     pub fn new() -> Self {
-        Self { qgroup: None }
+        Self {
+            qgroup: None,
+            readonly: false,
+            follow_symlinks: true,
+            owner: None,
+            idempotent: false,
+        }
     }
 
+    /// Adds the new subvolume to the qgroups of the specifier.
+    ///
+    /// The qgroups must exist, so quotas must be enabled on the filesystem,
+    /// which can be checked with [`quotas_enabled`]. Otherwise, the creation
+    /// fails with [`SUBVOL_CREATE_FAILED`](ErrorKind::SUBVOL_CREATE_FAILED)
+    /// (`EINVAL` or `ENOENT`, depending on the kernel), or on kernels which
+    /// ignore inheritance while quotas are disabled, succeeds without adding
+    /// the subvolume to any qgroup.
     pub fn qgroup(&mut self, qgroup: Option<QgroupInherit>) -> &mut Self {
         self.qgroup = qgroup;
         self
     }
 
+    /// When true, make the new subvolume read-only.
+    ///
+    /// The kernel cannot create a read-only subvolume directly, so it is made
+    /// read-only right after being created, leaving a brief window where it is
+    /// writable. If that fails, the new subvolume is deleted again.
+    pub fn readonly(&mut self, readonly: bool) -> &mut Self {
+        self.readonly = readonly;
+        self
+    }
+
+    /// When false, refuse to create the subvolume with `ELOOP` if the final
+    /// component of the path is a symbolic link. Defaults to true.
+    pub fn follow_symlinks(&mut self, follow_symlinks: bool) -> &mut Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Change the owner of the new subvolume to `uid` and `gid` right after
+    /// creating it. If that fails, the new subvolume is deleted again and an
+    /// error of kind [`CHOWN_FAILED`](ErrorKind::CHOWN_FAILED) is returned.
+    ///
+    /// This requires appropriate privilege (`CAP_CHOWN`).
+    pub fn owner(&mut self, uid: u32, gid: u32) -> &mut Self {
+        self.owner = Some((uid, gid));
+        self
+    }
+
+    /// When true, succeed without doing anything if a subvolume already exists
+    /// at the path. It is still an error if something other than a subvolume
+    /// exists there. Defaults to false.
+    ///
+    /// The existing subvolume is left as is, even if it does not match the
+    /// other options. This is best-effort: the path is only checked after
+    /// creating fails with `EEXIST`, and may change again afterwards.
+    pub fn idempotent(&mut self, idempotent: bool) -> &mut Self {
+        self.idempotent = idempotent;
+        self
+    }
+
     /// Creates a new subvolume.
     pub fn create<P: AsRef<Path>>(&mut self, path: P) -> Result<(), Error> {
+        if !self.follow_symlinks {
+            check_not_symlink(path.as_ref(), ErrorKind::SUBVOL_CREATE_FAILED)?;
+        }
         let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
         let flags: c_int = 0;
 
@@ -165,10 +844,33 @@ impl CreateSubvolumeOptions {
             ffi::btrfs_util_create_subvolume(cpath.as_ptr(), flags, std::ptr::null_mut(), cqgroup)
         };
         if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
-            Err(Error::new(errcode))
-        } else {
-            Ok(())
+            let err = Error::new(errcode);
+            if self.idempotent
+                && err.errno() == libc::EEXIST
+                && subvolume_existence(&path) == Ok(Existence::Subvolume)
+            {
+                return Ok(());
+            }
+            return Err(err);
         }
+
+        // A read-only subvolume cannot be chowned, so this must come first.
+        if let Some((uid, gid)) = self.owner {
+            if let Err(e) = std::os::unix::fs::chown(&path, Some(uid), Some(gid)) {
+                let _ = delete_subvolume(&path);
+                return Err(Error::with_errno(
+                    ErrorKind::CHOWN_FAILED,
+                    e.raw_os_error().unwrap_or(0),
+                ));
+            }
+        }
+        if self.readonly {
+            if let Err(err) = set_subvolume_read_only(&path, true) {
+                let _ = delete_subvolume(&path);
+                return Err(err);
+            }
+        }
+        Ok(())
     }
 }
 
@@ -178,10 +880,14 @@ pub fn create_subvolume<P: AsRef<Path>>(path: P) -> Result<(), Error> {
 }
 
 /// Options to create snapshots
+#[derive(Clone)]
 pub struct CreateSnapshotOptions {
     qgroup: Option<QgroupInherit>,
     readonly: bool,
     recursive: bool,
+    follow_symlinks: bool,
+    match_source_read_only: bool,
+    qgroup_limit: Option<u64>,
 }
 
 impl CreateSnapshotOptions {
@@ -190,6 +896,9 @@ impl CreateSnapshotOptions {
             qgroup: None,
             readonly: false,
             recursive: false,
+            follow_symlinks: true,
+            match_source_read_only: false,
+            qgroup_limit: None,
         }
     }
 
@@ -198,6 +907,19 @@ impl CreateSnapshotOptions {
         self
     }
 
+    /// Limits the space the snapshot may reference to `max_referenced` bytes,
+    /// like [`set_qgroup_limit`]. With [`recursive`](Self::recursive), only the
+    /// top snapshot is limited.
+    ///
+    /// The limit is set right after the snapshot is created, so it is briefly
+    /// unlimited. If setting the limit fails, e.g. with `ENOTCONN` because
+    /// quotas are disabled, the snapshot is left in place and the error is
+    /// returned.
+    pub fn with_qgroup_limit(&mut self, max_referenced: u64) -> &mut Self {
+        self.qgroup_limit = Some(max_referenced);
+        self
+    }
+
     pub fn readonly(&mut self, readonly: bool) -> &mut Self {
         self.readonly = readonly;
         self
@@ -208,40 +930,161 @@ impl CreateSnapshotOptions {
         self
     }
 
+    /// When false, refuse to create the snapshot with `ELOOP` if the final
+    /// component of the source or destination path is a symbolic link.
+    /// Defaults to true.
+    pub fn follow_symlinks(&mut self, follow_symlinks: bool) -> &mut Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// When true, make the snapshot read-only if and only if the source is
+    /// read-only. This takes precedence over [`readonly`](Self::readonly).
+    /// Defaults to false.
+    pub fn match_source_read_only(&mut self, match_source_read_only: bool) -> &mut Self {
+        self.match_source_read_only = match_source_read_only;
+        self
+    }
+
     /// Creates a new snapshot from a source subvolume.
+    ///
+    /// Returns an error of kind [`CROSS_FILESYSTEM`](ErrorKind::CROSS_FILESYSTEM)
+    /// if the source and the destination are on different filesystems.
     pub fn create<P: AsRef<Path>, Q: AsRef<Path>>(
         &mut self,
         source: P,
         path: Q,
     ) -> Result<(), Error> {
-        let csource = CString::new(source.as_ref().as_os_str().as_bytes()).unwrap();
-        let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
-
-        let mut flags: c_int = 0;
-        if self.readonly {
-            flags |= ffi::BTRFS_UTIL_CREATE_SNAPSHOT_READ_ONLY as c_int;
+        if !self.follow_symlinks {
+            check_not_symlink(source.as_ref(), ErrorKind::SNAP_CREATE_FAILED)?;
+            check_not_symlink(path.as_ref(), ErrorKind::SNAP_CREATE_FAILED)?;
         }
-        if self.recursive {
-            flags |= ffi::BTRFS_UTIL_CREATE_SNAPSHOT_RECURSIVE as c_int;
+        let parent = match path.as_ref().parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        // Leave any failure to resolve the filesystems to the ioctl, whose
+        // EXDEV is mapped below as well.
+        if let Ok(false) = filesystem::same_filesystem(source.as_ref(), parent) {
+            return Err(Error::with_errno(ErrorKind::CROSS_FILESYSTEM, libc::EXDEV));
         }
+        let csource = CString::new(source.as_ref().as_os_str().as_bytes()).unwrap();
+        let cpath = CString::new(path.as_ref().as_os_str().as_bytes()).unwrap();
 
-        let cqgroup: *mut ffi::btrfs_util_qgroup_inherit = if let Some(qg) = &self.qgroup {
-            qg.as_ptr()
+        let readonly = if self.match_source_read_only {
+            subvolume_read_only(source.as_ref())?
         } else {
-            std::ptr::null_mut()
+            self.readonly
         };
+
         unsafe {
             let errcode = ffi::btrfs_util_create_snapshot(
                 csource.as_ptr(),
                 cpath.as_ptr(),
-                flags,
+                self.flags(readonly),
                 std::ptr::null_mut(),
-                cqgroup,
+                self.qgroup_ptr(),
             );
+            if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+                let err = Error::new(errcode);
+                // The kernel refuses snapshots into another filesystem.
+                if err.errno() == libc::EXDEV {
+                    return Err(Error::with_errno(ErrorKind::CROSS_FILESYSTEM, libc::EXDEV));
+                }
+                return Err(err);
+            }
+        }
+        if let Some(max_referenced) = self.qgroup_limit {
+            qgroup::set_qgroup_limit(path, max_referenced)?;
+        }
+        Ok(())
+    }
+
+    /// Creates a new snapshot of the subvolume opened as `source_fd`, named
+    /// `name` in the directory opened as `dir_fd`, without resolving any path.
+    ///
+    /// Both file descriptors may be opened read-only, but not with `O_PATH`.
+    /// The caller needs write and search permission on the directory and must
+    /// own the source subvolume, unless it has appropriate privilege
+    /// (`CAP_FOWNER`).
+    ///
+    /// Returns an error of kind [`INVALID_ARGUMENT`](ErrorKind::INVALID_ARGUMENT)
+    /// if `name` is empty, `.`, `..`, or contains a `/` or a NUL byte.
+    pub fn create_fd_at<F: AsRawFd, G: AsRawFd>(
+        &mut self,
+        source_fd: &F,
+        dir_fd: &G,
+        name: &OsStr,
+    ) -> Result<(), Error> {
+        let bytes = name.as_bytes();
+        if bytes.is_empty() || bytes == b"." || bytes == b".." || bytes.contains(&b'/') {
+            return Err(Error::with_errno(ErrorKind::INVALID_ARGUMENT, libc::EINVAL));
+        }
+        let cname = CString::new(bytes)
+            .map_err(|_| Error::with_errno(ErrorKind::INVALID_ARGUMENT, libc::EINVAL))?;
+
+        let readonly = if self.match_source_read_only {
+            let mut ret = false;
+            let errcode = unsafe {
+                ffi::btrfs_util_get_subvolume_read_only_fd(source_fd.as_raw_fd(), &mut ret)
+            };
             if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
                 return Err(Error::new(errcode));
             }
+            ret
+        } else {
+            self.readonly
+        };
+
+        let errcode = unsafe {
+            ffi::btrfs_util_create_snapshot_fd2(
+                source_fd.as_raw_fd(),
+                dir_fd.as_raw_fd(),
+                cname.as_ptr(),
+                self.flags(readonly),
+                std::ptr::null_mut(),
+                self.qgroup_ptr(),
+            )
+        };
+        if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+            return Err(Error::new(errcode));
+        }
+        if let Some(max_referenced) = self.qgroup_limit {
+            let raw = unsafe {
+                libc::openat(
+                    dir_fd.as_raw_fd(),
+                    cname.as_ptr(),
+                    libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+                )
+            };
+            if raw < 0 {
+                return Err(Error::with_errno(
+                    ErrorKind::OPEN_FAILED,
+                    io::Error::last_os_error().raw_os_error().unwrap_or(0),
+                ));
+            }
+            let snapshot = unsafe { OwnedFd::from_raw_fd(raw) };
+            qgroup::set_qgroup_limit_fd(snapshot.as_raw_fd(), max_referenced)?;
         }
         Ok(())
     }
+
+    fn flags(&self, readonly: bool) -> c_int {
+        let mut flags: c_int = 0;
+        if readonly {
+            flags |= ffi::BTRFS_UTIL_CREATE_SNAPSHOT_READ_ONLY as c_int;
+        }
+        if self.recursive {
+            flags |= ffi::BTRFS_UTIL_CREATE_SNAPSHOT_RECURSIVE as c_int;
+        }
+        flags
+    }
+
+    fn qgroup_ptr(&self) -> *mut ffi::btrfs_util_qgroup_inherit {
+        if let Some(qg) = &self.qgroup {
+            qg.as_ptr()
+        } else {
+            std::ptr::null_mut()
+        }
+    }
 }
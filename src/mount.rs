@@ -0,0 +1,210 @@
+use std::{
+    ffi::OsString,
+    fs,
+    os::unix::prelude::OsStringExt,
+    path::{Path, PathBuf},
+};
+
+use crate::{subvolume_id, Error, ErrorKind};
+
+/// An entry of `/proc/self/mountinfo`.
+struct MountInfo {
+    dev: String,
+    mount_point: PathBuf,
+    fstype: String,
+    super_options: String,
+}
+
+impl MountInfo {
+    fn parse(line: &str) -> Option<Self> {
+        let (mount, fs) = line.split_once(" - ")?;
+        let mut mount = mount.split(' ');
+        let dev = mount.nth(2)?.to_owned();
+        let mount_point = unescape(mount.nth(1)?);
+        let mut fs = fs.split(' ');
+        let fstype = fs.next()?.to_owned();
+        let super_options = fs.nth(1).unwrap_or_default().to_owned();
+        Some(Self {
+            dev,
+            mount_point,
+            fstype,
+            super_options,
+        })
+    }
+
+    /// Returns the ID of the mounted subvolume from the `subvolid=` option.
+    fn subvolid(&self) -> Option<u64> {
+        parse_btrfs_mount_options(&self.super_options).subvolid
+    }
+}
+
+/// The subvolume options of a Btrfs mount, see [`parse_btrfs_mount_options`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BtrfsMountOptions {
+    /// The path of the mounted subvolume from the `subvol=` option, relative
+    /// to the filesystem root, e.g. `/` for the root subvolume.
+    pub subvol: Option<PathBuf>,
+    /// The ID of the mounted subvolume from the `subvolid=` option.
+    pub subvolid: Option<u64>,
+}
+
+/// Parses the subvolume options from comma-separated Btrfs mount options, as
+/// found in `/proc/self/mountinfo` or the output of `findmnt`.
+///
+/// As with the kernel, the last `subvol=` and `subvolid=` options win. Octal
+/// escapes (e.g. `\040` for a space) are decoded, and an unparsable
+/// `subvolid=` is ignored.
+pub fn parse_btrfs_mount_options(opts: &str) -> BtrfsMountOptions {
+    let mut ret = BtrfsMountOptions::default();
+    for opt in opts.split(',') {
+        if let Some(subvolid) = opt.strip_prefix("subvolid=") {
+            ret.subvolid = subvolid.parse().ok();
+        } else if let Some(subvol) = opt.strip_prefix("subvol=") {
+            ret.subvol = Some(unescape(subvol));
+        }
+    }
+    ret
+}
+
+/// Decodes the octal escapes (e.g. `\040` for a space) used by the kernel.
+fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            let code = std::str::from_utf8(&bytes[i + 1..i + 4])
+                .ok()
+                .and_then(|code| u8::from_str_radix(code, 8).ok());
+            if let Some(c) = code {
+                ret.push(c);
+                i += 4;
+                continue;
+            }
+        }
+        ret.push(bytes[i]);
+        i += 1;
+    }
+    PathBuf::from(OsString::from_vec(ret))
+}
+
+fn mountinfo() -> Result<Vec<MountInfo>, Error> {
+    let content = fs::read_to_string("/proc/self/mountinfo")
+        .map_err(|e| Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0)))?;
+    Ok(content.lines().filter_map(MountInfo::parse).collect())
+}
+
+/// Returns the mount of the Btrfs filesystem containing the `path`.
+fn containing_mount<P: AsRef<Path>>(path: P, mounts: &[MountInfo]) -> Result<&MountInfo, Error> {
+    let path = fs::canonicalize(path)
+        .map_err(|e| Error::with_errno(ErrorKind::STAT_FAILED, e.raw_os_error().unwrap_or(0)))?;
+
+    // The last of the longest matching mount points is the visible one.
+    mounts
+        .iter()
+        .filter(|m| path.starts_with(&m.mount_point))
+        .max_by_key(|m| m.mount_point.as_os_str().len())
+        .filter(|m| m.fstype == "btrfs")
+        .ok_or_else(|| Error::with_errno(ErrorKind::NOT_BTRFS, libc::EINVAL))
+}
+
+/// Returns the mount points and mounted subvolume IDs of every mount of the
+/// Btrfs filesystem containing the `path`.
+fn btrfs_mounts<P: AsRef<Path>>(path: P) -> Result<Vec<(PathBuf, u64)>, Error> {
+    let mounts = mountinfo()?;
+    let containing = containing_mount(path, &mounts)?;
+
+    Ok(mounts
+        .iter()
+        .filter(|m| m.fstype == "btrfs" && m.dev == containing.dev)
+        .filter_map(|m| {
+            let id = match m.subvolid() {
+                Some(id) => id,
+                None => subvolume_id(&m.mount_point).ok()?,
+            };
+            Some((m.mount_point.clone(), id))
+        })
+        .collect())
+}
+
+/// Returns whether the subvolume with the given `id` on the filesystem
+/// containing the `mount` is mounted in the current mount namespace.
+///
+/// Mounts are read from `/proc/self/mountinfo` and matched by their
+/// `subvolid=` option, falling back to the subvolume containing the mount
+/// point for kernels which do not report it.
+pub fn is_subvolume_mounted<P: AsRef<Path>>(mount: P, id: u64) -> Result<bool, Error> {
+    Ok(btrfs_mounts(mount)?
+        .iter()
+        .any(|(_, mounted_id)| *mounted_id == id))
+}
+
+/// Returns where the subvolume with the given `id` on the filesystem
+/// containing the `mount` is mounted in the current mount namespace, or
+/// [`None`] if it is not mounted. If it is mounted more than once, the first
+/// mount listed in `/proc/self/mountinfo` is returned.
+///
+/// See [`is_subvolume_mounted`] for how mounts are matched.
+pub fn mountpoint_of<P: AsRef<Path>>(mount: P, id: u64) -> Result<Option<PathBuf>, Error> {
+    Ok(btrfs_mounts(mount)?
+        .into_iter()
+        .find(|(_, mounted_id)| *mounted_id == id)
+        .map(|(mount_point, _)| mount_point))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{parse_btrfs_mount_options, BtrfsMountOptions, MountInfo};
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let info = MountInfo::parse(
+            "36 35 0:31 /@home /mnt/my\\040home rw,noatime master:1 - btrfs /dev/sda1 rw,ssd,subvolid=256,subvol=/@home",
+        )
+        .unwrap();
+        assert_eq!(info.dev, "0:31");
+        assert_eq!(info.mount_point, PathBuf::from("/mnt/my home"));
+        assert_eq!(info.fstype, "btrfs");
+        assert_eq!(info.subvolid(), Some(256));
+    }
+
+    #[test]
+    fn test_parse_mountinfo_root() {
+        let info = MountInfo::parse(
+            "25 1 0:23 / / rw,relatime shared:1 - btrfs /dev/vda2 rw,seclabel,subvolid=5,subvol=/",
+        )
+        .unwrap();
+        assert_eq!(info.mount_point, PathBuf::from("/"));
+        assert_eq!(info.subvolid(), Some(5));
+    }
+
+    #[test]
+    fn test_parse_mountinfo_no_subvolid() {
+        let info = MountInfo::parse("22 1 8:1 / /boot rw,relatime - ext4 /dev/sda1 rw").unwrap();
+        assert_eq!(info.fstype, "ext4");
+        assert_eq!(info.subvolid(), None);
+    }
+
+    #[test]
+    fn test_parse_btrfs_mount_options() {
+        let opts = parse_btrfs_mount_options("rw,seclabel,subvolid=5,subvol=/");
+        assert_eq!(opts.subvol, Some(PathBuf::from("/")));
+        assert_eq!(opts.subvolid, Some(5));
+
+        let opts = parse_btrfs_mount_options("rw,subvol=/@,subvol=/@home,subvolid=256");
+        assert_eq!(opts.subvol, Some(PathBuf::from("/@home")));
+        assert_eq!(opts.subvolid, Some(256));
+
+        let opts = parse_btrfs_mount_options("subvol=/my\\040home,subvolid=x");
+        assert_eq!(opts.subvol, Some(PathBuf::from("/my home")));
+        assert_eq!(opts.subvolid, None);
+
+        assert_eq!(parse_btrfs_mount_options(""), BtrfsMountOptions::default());
+        assert_eq!(
+            parse_btrfs_mount_options("rw,relatime"),
+            BtrfsMountOptions::default()
+        );
+    }
+}
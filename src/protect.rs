@@ -0,0 +1,35 @@
+use std::collections::HashSet;
+
+use uuid::Uuid;
+
+/// A set of subvolumes, identified by UUID, which must not be deleted.
+///
+/// Btrfs has no notion of protected subvolumes, so this is only enforced by
+/// [`DeleteSubvolumeOptions::respect_protection`].
+///
+/// [`DeleteSubvolumeOptions::respect_protection`]: crate::DeleteSubvolumeOptions::respect_protection
+#[derive(Debug, Clone, Default)]
+pub struct ProtectedSet(HashSet<Uuid>);
+
+impl ProtectedSet {
+    pub fn new() -> Self {
+        Self(HashSet::new())
+    }
+
+    /// Protects the subvolume with the given UUID. Returns whether it was not
+    /// already protected.
+    pub fn protect(&mut self, uuid: Uuid) -> bool {
+        self.0.insert(uuid)
+    }
+
+    /// Stops protecting the subvolume with the given UUID. Returns whether it
+    /// was protected.
+    pub fn unprotect(&mut self, uuid: Uuid) -> bool {
+        self.0.remove(&uuid)
+    }
+
+    /// Returns whether the subvolume with the given UUID is protected.
+    pub fn is_protected(&self, uuid: Uuid) -> bool {
+        self.0.contains(&uuid)
+    }
+}
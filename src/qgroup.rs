@@ -1,15 +1,232 @@
-use std::{ptr, slice};
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io, mem,
+    os::{
+        fd::{AsRawFd, RawFd},
+        raw::c_int,
+    },
+    path::Path,
+    ptr, slice,
+};
 
-use crate::Error;
+use crate::{Error, ErrorKind};
+
+/// `_IOWR(BTRFS_IOCTL_MAGIC, 17, struct btrfs_ioctl_search_args)`
+const BTRFS_IOC_TREE_SEARCH: u64 = 0xd000_9411;
+/// Size of `struct btrfs_ioctl_search_args`.
+const BTRFS_SEARCH_ARGS_SIZE: usize = 4096;
+/// Size of `struct btrfs_ioctl_search_key`, which precedes the result buffer.
+const BTRFS_SEARCH_KEY_SIZE: usize = 104;
+/// Size of `struct btrfs_ioctl_search_header`, which precedes each item.
+const BTRFS_SEARCH_HEADER_SIZE: usize = 32;
+
+const BTRFS_QUOTA_TREE_OBJECTID: u64 = 8;
+const BTRFS_QGROUP_STATUS_KEY: u32 = 240;
+const BTRFS_QGROUP_INFO_KEY: u32 = 242;
+/// Flag in `struct btrfs_qgroup_status_item` set while quotas are enabled.
+const BTRFS_QGROUP_STATUS_FLAG_ON: u64 = 1 << 0;
+
+/// `_IOR(BTRFS_IOCTL_MAGIC, 43, struct btrfs_ioctl_qgroup_limit_args)`
+const BTRFS_IOC_QGROUP_LIMIT: u64 = 0x8030_942b;
+/// Flag in `struct btrfs_qgroup_limit` to set `max_rfer`.
+const BTRFS_QGROUP_LIMIT_MAX_RFER: u64 = 1 << 0;
+
+/// `struct btrfs_ioctl_qgroup_limit_args`
+#[repr(C)]
+#[derive(Default)]
+struct QgroupLimitArgs {
+    qgroupid: u64,
+    flags: u64,
+    max_rfer: u64,
+    max_excl: u64,
+    rsv_rfer: u64,
+    rsv_excl: u64,
+}
+
+/// `struct btrfs_ioctl_search_key`
+#[repr(C)]
+struct SearchKey {
+    tree_id: u64,
+    min_objectid: u64,
+    max_objectid: u64,
+    min_offset: u64,
+    max_offset: u64,
+    min_transid: u64,
+    max_transid: u64,
+    min_type: u32,
+    max_type: u32,
+    nr_items: u32,
+    unused: u32,
+    unused1: u64,
+    unused2: u64,
+    unused3: u64,
+    unused4: u64,
+}
+
+/// `struct btrfs_ioctl_search_args`
+#[repr(C)]
+struct SearchArgs {
+    key: SearchKey,
+    buf: [u8; BTRFS_SEARCH_ARGS_SIZE - BTRFS_SEARCH_KEY_SIZE],
+}
+
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    u64::from_ne_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_ne_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+/// Calls `f` with the key offset and the data of each item of type
+/// `item_type` in the quota tree, in key order. Returns `false` without
+/// calling `f` if the filesystem has no quota tree, i.e. quotas are disabled.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub(crate) fn search_quota_tree<F>(fd: RawFd, item_type: u32, mut f: F) -> Result<bool, Error>
+where
+    F: FnMut(u64, &[u8]),
+{
+    let mut args: SearchArgs = unsafe { mem::zeroed() };
+    args.key.tree_id = BTRFS_QUOTA_TREE_OBJECTID;
+    args.key.min_type = item_type;
+    args.key.max_type = item_type;
+    args.key.max_offset = u64::MAX;
+    args.key.max_transid = u64::MAX;
+    loop {
+        args.key.nr_items = u32::MAX;
+        let ret = unsafe { libc::ioctl(fd, BTRFS_IOC_TREE_SEARCH as _, &mut args) };
+        if ret < 0 {
+            let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+            if errno == libc::ENOENT {
+                return Ok(false);
+            }
+            return Err(Error::with_errno(ErrorKind::SEARCH_FAILED, errno));
+        }
+        if args.key.nr_items == 0 {
+            return Ok(true);
+        }
+        let mut pos = 0;
+        let mut offset = 0;
+        for _ in 0..args.key.nr_items {
+            let header = &args.buf[pos..pos + BTRFS_SEARCH_HEADER_SIZE];
+            offset = read_u64(header, 16);
+            let len = read_u32(header, 28) as usize;
+            pos += BTRFS_SEARCH_HEADER_SIZE;
+            if read_u32(header, 24) == item_type {
+                f(offset, &args.buf[pos..pos + len]);
+            }
+            pos += len;
+        }
+        if offset == u64::MAX {
+            return Ok(true);
+        }
+        args.key.min_offset = offset + 1;
+    }
+}
+
+/// Returns whether quotas (qgroups) are enabled on the Btrfs filesystem
+/// containing the `path`.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn quotas_enabled<P: AsRef<Path>>(path: P) -> Result<bool, Error> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .map_err(|e| Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0)))?;
+    let mut on = false;
+    search_quota_tree(file.as_raw_fd(), BTRFS_QGROUP_STATUS_KEY, |_, item| {
+        // `struct btrfs_qgroup_status_item`: version, generation, flags, rescan.
+        if item.len() >= 24 {
+            let flags = u64::from_le_bytes(item[16..24].try_into().unwrap());
+            on = flags & BTRFS_QGROUP_STATUS_FLAG_ON != 0;
+        }
+    })?;
+    Ok(on)
+}
+
+/// Limits the space the subvolume containing the `path` may reference to
+/// `max_referenced` bytes, by setting the limit of its level 0 qgroup. A limit
+/// of [`u64::MAX`] removes the limit.
+///
+/// Returns an error of kind [`QGROUP_LIMIT_FAILED`](ErrorKind::QGROUP_LIMIT_FAILED)
+/// with `ENOTCONN` if quotas are disabled, see [`quotas_enabled`].
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn set_qgroup_limit<P: AsRef<Path>>(path: P, max_referenced: u64) -> Result<(), Error> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .map_err(|e| Error::with_errno(ErrorKind::OPEN_FAILED, e.raw_os_error().unwrap_or(0)))?;
+    set_qgroup_limit_fd(file.as_raw_fd(), max_referenced)
+}
+
+/// Like [`set_qgroup_limit`], for the subvolume containing the open `fd`.
+pub(crate) fn set_qgroup_limit_fd(fd: RawFd, max_referenced: u64) -> Result<(), Error> {
+    // A qgroup ID of zero refers to the subvolume containing the file.
+    let mut args = QgroupLimitArgs {
+        flags: BTRFS_QGROUP_LIMIT_MAX_RFER,
+        max_rfer: max_referenced,
+        ..Default::default()
+    };
+    let ret = unsafe { libc::ioctl(fd, BTRFS_IOC_QGROUP_LIMIT as _, &mut args) };
+    if ret < 0 {
+        return Err(Error::with_errno(
+            ErrorKind::QGROUP_LIMIT_FAILED,
+            io::Error::last_os_error().raw_os_error().unwrap_or(0),
+        ));
+    }
+    Ok(())
+}
+
+/// Space accounted to a qgroup, as tracked by Btrfs quotas.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct QgroupUsage {
+    /// Bytes referenced by the qgroup, including extents shared with others.
+    pub referenced: u64,
+    /// Bytes referenced only by the qgroup, which deleting it would free.
+    pub exclusive: u64,
+}
+
+/// Returns the usage of every level 0 qgroup, keyed by subvolume ID, or
+/// [`None`] if quotas are disabled.
+pub(crate) fn subvolume_usage(fd: RawFd) -> Result<Option<HashMap<u64, QgroupUsage>>, Error> {
+    let mut usage = HashMap::new();
+    let enabled = search_quota_tree(fd, BTRFS_QGROUP_INFO_KEY, |qgroupid, item| {
+        // Level 0 qgroups have the subvolume ID as the qgroup ID. The item is
+        // `struct btrfs_qgroup_info_item`, stored little-endian:
+        // generation, rfer, rfer_cmpr, excl, excl_cmpr.
+        if qgroupid >> 48 == 0 && item.len() >= 40 {
+            usage.insert(
+                qgroupid,
+                QgroupUsage {
+                    referenced: u64::from_le_bytes(item[8..16].try_into().unwrap()),
+                    exclusive: u64::from_le_bytes(item[24..32].try_into().unwrap()),
+                },
+            );
+        }
+    })?;
+    Ok(enabled.then_some(usage))
+}
 
 /// qgroup inheritance specifier.
 pub struct QgroupInherit(*mut ffi::btrfs_util_qgroup_inherit);
 
 impl QgroupInherit {
     pub fn new() -> Result<Self, Error> {
+        Self::with_flags(0)
+    }
+
+    /// Returns a builder to create a qgroup inheritance specifier in one step.
+    pub fn builder() -> QgroupInheritBuilder {
+        QgroupInheritBuilder::new()
+    }
+
+    fn with_flags(flags: u32) -> Result<Self, Error> {
         let mut ret: *mut ffi::btrfs_util_qgroup_inherit = ptr::null_mut();
 
-        let errcode = unsafe { ffi::btrfs_util_create_qgroup_inherit(0, &mut ret) };
+        let errcode = unsafe { ffi::btrfs_util_create_qgroup_inherit(flags as c_int, &mut ret) };
         if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
             Err(Error::new(errcode))
         } else {
@@ -45,6 +262,43 @@ impl QgroupInherit {
     pub fn as_ptr(&self) -> *mut ffi::btrfs_util_qgroup_inherit {
         self.0
     }
+
+    /// Consumes the specifier, returning the raw pointer. The caller is
+    /// responsible for freeing it with `btrfs_util_destroy_qgroup_inherit`.
+    pub fn into_raw(self) -> *mut ffi::btrfs_util_qgroup_inherit {
+        let ptr = self.0;
+        mem::forget(self);
+        ptr
+    }
+
+    /// Creates a specifier from a raw pointer, taking ownership of it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a valid qgroup inheritance specifier created by
+    /// libbtrfsutil, e.g. returned by [`into_raw`](Self::into_raw), which is not
+    /// owned by anything else.
+    pub unsafe fn from_raw(ptr: *mut ffi::btrfs_util_qgroup_inherit) -> Self {
+        QgroupInherit(ptr)
+    }
+}
+
+/// Creates a new specifier with the same qgroups.
+///
+/// # Panics
+///
+/// Panics if libbtrfsutil fails to allocate the new specifier.
+impl Clone for QgroupInherit {
+    fn clone(&self) -> Self {
+        // libbtrfsutil rejects any flags, so only the groups need copying.
+        let mut ret =
+            QgroupInherit::new().expect("failed to allocate qgroup inheritance specifier");
+        for &qgroup_id in self.groups() {
+            ret.add_group(qgroup_id)
+                .expect("failed to allocate qgroup inheritance specifier");
+        }
+        ret
+    }
 }
 
 impl Drop for QgroupInherit {
@@ -55,6 +309,47 @@ impl Drop for QgroupInherit {
     }
 }
 
+/// A builder to create a [`QgroupInherit`]
+#[derive(Clone, Default)]
+pub struct QgroupInheritBuilder {
+    flags: u32,
+    groups: Vec<u64>,
+}
+
+impl QgroupInheritBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Flags for the specifier. Reserved by libbtrfsutil, which currently
+    /// rejects anything other than zero (the default).
+    pub fn flags(&mut self, flags: u32) -> &mut Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Adds inheritance from a qgroup.
+    pub fn group(&mut self, qgroup_id: u64) -> &mut Self {
+        self.groups.push(qgroup_id);
+        self
+    }
+
+    /// Adds inheritance from each of the qgroups.
+    pub fn groups<I: IntoIterator<Item = u64>>(&mut self, qgroup_ids: I) -> &mut Self {
+        self.groups.extend(qgroup_ids);
+        self
+    }
+
+    /// Creates the qgroup inheritance specifier.
+    pub fn build(&self) -> Result<QgroupInherit, Error> {
+        let mut inherit = QgroupInherit::with_flags(self.flags)?;
+        for &qgroup_id in &self.groups {
+            inherit.add_group(qgroup_id)?;
+        }
+        Ok(inherit)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::QgroupInherit;
@@ -75,4 +370,33 @@ mod tests {
         inherit.add_group(3).unwrap();
         assert_eq!(inherit.groups(), [1, 2, 3]);
     }
+
+    #[test]
+    fn test_builder() {
+        let inherit = QgroupInherit::builder()
+            .group(1)
+            .groups([2, 3])
+            .build()
+            .unwrap();
+        assert_eq!(inherit.groups(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_clone() {
+        let mut inherit = QgroupInherit::new().unwrap();
+        inherit.add_group(1).unwrap();
+        let mut clone = inherit.clone();
+        assert_ne!(clone.as_ptr(), inherit.as_ptr());
+        clone.add_group(2).unwrap();
+        assert_eq!(inherit.groups(), [1]);
+        assert_eq!(clone.groups(), [1, 2]);
+    }
+
+    #[test]
+    fn test_into_raw() {
+        let mut inherit = QgroupInherit::new().unwrap();
+        inherit.add_group(1).unwrap();
+        let inherit = unsafe { QgroupInherit::from_raw(inherit.into_raw()) };
+        assert_eq!(inherit.groups(), [1]);
+    }
 }
@@ -0,0 +1,176 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use crate::SubvolumeInfo;
+
+/// Snapshots to keep and to delete, as returned by [`select_snapshots_by_age`].
+pub type Selection<'a> = (
+    Vec<&'a (PathBuf, SubvolumeInfo)>,
+    Vec<&'a (PathBuf, SubvolumeInfo)>,
+);
+
+/// Splits `snapshots` into the ones to keep and the ones to delete according
+/// to consecutive retention windows going back from now.
+///
+/// The first window spans the last `keep[0]`, the second the `keep[1]` before
+/// it, and so on, so keeping the last 7 daily and 4 weekly snapshots is 7
+/// windows of a day followed by 4 windows of a week. The newest snapshot
+/// [`created`] in each window is kept, ties being broken by the creation
+/// transaction ID, then the subvolume ID. Snapshots older than all windows, or
+/// not the newest in their window, are deleted. Snapshots created in the
+/// future, e.g. because of clock changes, are always kept.
+///
+/// Both lists keep the order of `snapshots`.
+///
+/// [`created`]: SubvolumeInfo::created
+pub fn select_snapshots_by_age<'a>(
+    snapshots: &'a [(PathBuf, SubvolumeInfo)],
+    keep: &[Duration],
+) -> Selection<'a> {
+    select_at(snapshots, keep, SystemTime::now())
+}
+
+fn select_at<'a>(
+    snapshots: &'a [(PathBuf, SubvolumeInfo)],
+    keep: &[Duration],
+    now: SystemTime,
+) -> Selection<'a> {
+    // The index of the newest snapshot in each window.
+    let mut newest: Vec<Option<usize>> = vec![None; keep.len()];
+    let mut kept = vec![false; snapshots.len()];
+    for (index, (_, info)) in snapshots.iter().enumerate() {
+        let age = match now.duration_since(info.created()) {
+            Ok(age) => age,
+            Err(_) => {
+                kept[index] = true;
+                continue;
+            }
+        };
+        let mut end = Duration::ZERO;
+        for (window, &width) in keep.iter().enumerate() {
+            end = end.saturating_add(width);
+            if age < end {
+                let is_newer = match newest[window] {
+                    Some(other) => {
+                        let other = &snapshots[other].1;
+                        (other.created(), other.otransid(), other.id())
+                            < (info.created(), info.otransid(), info.id())
+                    }
+                    None => true,
+                };
+                if is_newer {
+                    newest[window] = Some(index);
+                }
+                break;
+            }
+        }
+    }
+
+    for index in newest.into_iter().flatten() {
+        kept[index] = true;
+    }
+
+    let mut selection = (Vec::new(), Vec::new());
+    for (snapshot, keep) in snapshots.iter().zip(kept) {
+        if keep {
+            selection.0.push(snapshot);
+        } else {
+            selection.1.push(snapshot);
+        }
+    }
+    selection
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use super::select_at;
+    use crate::SubvolumeInfo;
+
+    const HOUR: i64 = 3600;
+    const DAY: i64 = 24 * HOUR;
+
+    fn secs(secs: i64) -> Duration {
+        Duration::from_secs(secs as u64)
+    }
+
+    fn snapshot(name: &str, otime: i64) -> (PathBuf, SubvolumeInfo) {
+        snapshot_with_otransid(name, otime, otime as u64)
+    }
+
+    fn snapshot_with_otransid(name: &str, otime: i64, otransid: u64) -> (PathBuf, SubvolumeInfo) {
+        let mut info = SubvolumeInfo::new();
+        info.0.otime.tv_sec = otime;
+        info.0.otransid = otransid;
+        (PathBuf::from(name), info)
+    }
+
+    fn names(snapshots: &[&(PathBuf, SubvolumeInfo)]) -> Vec<String> {
+        snapshots
+            .iter()
+            .map(|(path, _)| path.display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_select() {
+        let now = 100 * DAY;
+        let snapshots = [
+            snapshot("future", now + 60),
+            snapshot("today-1", now - 2 * HOUR),
+            snapshot("today-2", now - HOUR),
+            snapshot("yesterday", now - DAY - HOUR),
+            snapshot("last-week", now - 5 * DAY),
+            snapshot("old", now - 30 * DAY),
+        ];
+        let keep = [secs(DAY), secs(DAY), secs(7 * DAY)];
+        let now = SystemTime::UNIX_EPOCH + secs(now);
+        let (kept, deleted) = select_at(&snapshots, &keep, now);
+        assert_eq!(
+            names(&kept),
+            ["future", "today-2", "yesterday", "last-week"]
+        );
+        assert_eq!(names(&deleted), ["today-1", "old"]);
+    }
+
+    #[test]
+    fn test_select_by_creation_time() {
+        // Received snapshots get transaction IDs in the order they were
+        // received, which need not be the order they were created in.
+        let snapshots = [
+            snapshot_with_otransid("older", 2 * HOUR, 20),
+            snapshot_with_otransid("newer", 3 * HOUR, 10),
+            snapshot_with_otransid("tie-1", 5 * HOUR, 30),
+            snapshot_with_otransid("tie-2", 5 * HOUR, 40),
+        ];
+        let keep = [secs(4 * HOUR), secs(DAY)];
+        let now = SystemTime::UNIX_EPOCH + secs(8 * HOUR);
+        let (kept, deleted) = select_at(&snapshots, &keep, now);
+        assert_eq!(names(&kept), ["newer", "tie-2"]);
+        assert_eq!(names(&deleted), ["older", "tie-1"]);
+    }
+
+    #[test]
+    fn test_select_before_epoch() {
+        let snapshots = [snapshot("a", -2 * DAY), snapshot("b", -DAY)];
+        let keep = [secs(10 * DAY)];
+        let (kept, deleted) = select_at(&snapshots, &keep, SystemTime::UNIX_EPOCH);
+        assert_eq!(names(&kept), ["b"]);
+        assert_eq!(names(&deleted), ["a"]);
+    }
+
+    #[test]
+    fn test_select_no_windows() {
+        let snapshots = [snapshot("a", 0)];
+        let now = SystemTime::UNIX_EPOCH + secs(DAY);
+        let (kept, deleted) = select_at(&snapshots, &[], now);
+        assert!(kept.is_empty());
+        assert_eq!(names(&deleted), ["a"]);
+    }
+}
@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    default_subvolume, delete_subvolume, set_default_subvolume, subvolume_id, sync,
+    CreateSnapshotOptions, Error,
+};
+
+/// Describes what [`prepare_rollback`] did, so that it can be undone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollbackPlan {
+    path: PathBuf,
+    id: u64,
+    previous_default_id: u64,
+}
+
+impl RollbackPlan {
+    /// Returns the path of the newly created subvolume.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the ID of the newly created subvolume, which is now the default.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the ID of the default subvolume before the rollback.
+    pub fn previous_default_id(&self) -> u64 {
+        self.previous_default_id
+    }
+
+    /// Restores the previous default subvolume and syncs the filesystem.
+    ///
+    /// The subvolume created by [`prepare_rollback`] is left in place.
+    ///
+    /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+    pub fn revert(&self) -> Result<(), Error> {
+        set_default_subvolume(&self.path, self.previous_default_id)?;
+        sync(&self.path)
+    }
+}
+
+/// Prepares a rollback to the snapshot at `snapshot`.
+///
+/// This creates a writable snapshot of `snapshot` at `path`, makes it the
+/// default subvolume and syncs the filesystem, leaving only the reboot to the
+/// caller. If any step fails, the steps already done are undone on a
+/// best-effort basis and the original error is returned.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn prepare_rollback<P: AsRef<Path>, Q: AsRef<Path>>(
+    snapshot: P,
+    path: Q,
+) -> Result<RollbackPlan, Error> {
+    let path = path.as_ref();
+    let previous_default_id = default_subvolume(&snapshot)?;
+
+    CreateSnapshotOptions::new().create(&snapshot, path)?;
+
+    let id = match subvolume_id(path) {
+        Ok(id) => id,
+        Err(err) => {
+            let _ = delete_subvolume(path);
+            return Err(err);
+        }
+    };
+    if let Err(err) = set_default_subvolume(path, id) {
+        let _ = delete_subvolume(path);
+        return Err(err);
+    }
+    if let Err(err) = sync(path) {
+        let _ = set_default_subvolume(path, previous_default_id);
+        let _ = delete_subvolume(path);
+        return Err(err);
+    }
+
+    Ok(RollbackPlan {
+        path: path.to_path_buf(),
+        id,
+        previous_default_id,
+    })
+}
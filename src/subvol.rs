@@ -1,60 +1,106 @@
 use std::{
-    ffi::{CString, OsStr},
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    ffi::{CStr, CString, OsStr},
+    fmt,
+    mem::MaybeUninit,
     num::{NonZeroI64, NonZeroU64},
-    os::{raw::c_int, unix::prelude::OsStrExt},
+    ops::ControlFlow,
+    os::{
+        fd::{AsFd, AsRawFd, IntoRawFd, OwnedFd},
+        raw::c_int,
+        unix::prelude::OsStrExt,
+    },
+    panic,
     path::{Path, PathBuf},
     ptr,
+    sync::{
+        atomic::{self, AtomicUsize},
+        Arc,
+    },
+    thread,
     time::{Duration, SystemTime},
 };
 
 use uuid::Uuid;
 
-use crate::{Error, FS_TREE_OBJECTID};
+use crate::{
+    subvolume_info_with_id, with_ioctl_fd, Error, ErrorKind, BTRFS_ROOT_SUBVOL_RDONLY,
+    FS_TREE_OBJECTID,
+};
 
 /// Information about a Btrfs subvolume.
-#[derive(Debug, Clone)]
-pub struct SubvolumeInfo(ffi::btrfs_util_subvolume_info);
+#[derive(Clone)]
+pub struct SubvolumeInfo(pub(crate) ffi::btrfs_util_subvolume_info);
+
+/// The exact timestamps of a subvolume as `(seconds, nanoseconds)` since Unix
+/// Epoch. Unset timestamps are `(0, 0)`. See [`SubvolumeInfo::raw_timespecs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RawTimespecs {
+    pub ctime: (i64, i64),
+    pub otime: (i64, i64),
+    pub stime: (i64, i64),
+    pub rtime: (i64, i64),
+}
+
+/// The identity of a subvolume in snapshot and send/receive relationships.
+/// See [`SubvolumeInfo::lineage`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Lineage {
+    /// The UUID of the subvolume.
+    pub uuid: Uuid,
+    /// The UUID of the subvolume it is a snapshot of, if any.
+    pub parent_uuid: Option<Uuid>,
+    /// The UUID of the subvolume it was received from, if any.
+    pub received_uuid: Option<Uuid>,
+}
+
+impl Lineage {
+    /// Returns whether the subvolume was received, i.e. it has a
+    /// `received_uuid`.
+    pub fn is_received(&self) -> bool {
+        self.received_uuid.is_some()
+    }
+
+    /// Returns whether the subvolume is a snapshot, i.e. it has a
+    /// `parent_uuid`.
+    pub fn is_snapshot(&self) -> bool {
+        self.parent_uuid.is_some()
+    }
+}
+
+/// How a received subvolume was received. See [`SubvolumeInfo::received_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ReceivedInfo {
+    /// The UUID of the subvolume it was received from.
+    pub uuid: Uuid,
+    /// The transaction ID of the sent subvolume.
+    pub stransid: u64,
+    /// The transaction ID when it was received.
+    pub rtransid: u64,
+    /// The time when it was received.
+    pub rtime: SystemTime,
+}
 
 struct Timespec(ffi::timespec);
 impl From<Timespec> for SystemTime {
     fn from(ts: Timespec) -> Self {
-        let duration = Duration::new(ts.0.tv_sec as u64, ts.0.tv_nsec as u32);
-        SystemTime::UNIX_EPOCH + duration
+        // Times before the Unix Epoch have negative seconds and positive
+        // nanoseconds.
+        let nsec = Duration::from_nanos(ts.0.tv_nsec as u64);
+        if ts.0.tv_sec >= 0 {
+            SystemTime::UNIX_EPOCH + Duration::from_secs(ts.0.tv_sec as u64) + nsec
+        } else {
+            SystemTime::UNIX_EPOCH - Duration::from_secs(ts.0.tv_sec.unsigned_abs()) + nsec
+        }
     }
 }
 
 impl SubvolumeInfo {
     pub fn new() -> Self {
-        let inner: ffi::btrfs_util_subvolume_info = ffi::btrfs_util_subvolume_info {
-            id: 0,
-            parent_id: 0,
-            dir_id: 0,
-            flags: 0,
-            uuid: [0u8; 16],
-            parent_uuid: [0u8; 16],
-            received_uuid: [0u8; 16],
-            generation: 0,
-            ctransid: 0,
-            otransid: 0,
-            stransid: 0,
-            rtransid: 0,
-            ctime: ffi::timespec {
-                tv_sec: 0,
-                tv_nsec: 0,
-            },
-            otime: ffi::timespec {
-                tv_sec: 0,
-                tv_nsec: 0,
-            },
-            stime: ffi::timespec {
-                tv_sec: 0,
-                tv_nsec: 0,
-            },
-            rtime: ffi::timespec {
-                tv_sec: 0,
-                tv_nsec: 0,
-            },
-        };
+        // Zero the whole struct rather than each field, so that fields added
+        // by newer libbtrfsutil headers are zeroed as well.
+        let inner: ffi::btrfs_util_subvolume_info = unsafe { MaybeUninit::zeroed().assume_init() };
         SubvolumeInfo(inner)
     }
 
@@ -62,6 +108,11 @@ impl SubvolumeInfo {
         &mut self.0
     }
 
+    /// Returns the raw libbtrfsutil struct.
+    pub fn as_raw(&self) -> &ffi::btrfs_util_subvolume_info {
+        &self.0
+    }
+
     /// Returns the ID of this subvolume, unique across the filesystem.
     pub fn id(&self) -> u64 {
         self.0.id
@@ -91,6 +142,19 @@ impl SubvolumeInfo {
         self.0.flags
     }
 
+    /// Returns whether the read-only flag ([`BTRFS_ROOT_SUBVOL_RDONLY`]) is
+    /// set in the root item flags.
+    ///
+    /// This should always agree with [`subvolume_read_only`], as both read the
+    /// same root item flag. If the subvolume was changed since this
+    /// information was fetched, [`subvolume_read_only`] is authoritative.
+    ///
+    /// [`BTRFS_ROOT_SUBVOL_RDONLY`]: crate::BTRFS_ROOT_SUBVOL_RDONLY
+    /// [`subvolume_read_only`]: crate::subvolume_read_only
+    pub fn is_read_only(&self) -> bool {
+        self.0.flags & BTRFS_ROOT_SUBVOL_RDONLY != 0
+    }
+
     /// Returns the UUID of this subvolume.
     pub fn uuid(&self) -> Uuid {
         Uuid::from_bytes(self.0.uuid)
@@ -110,7 +174,8 @@ impl SubvolumeInfo {
     /// Returns the UUID of the subvolume this subvolume was received from, or
     /// [`None`] if this subvolume was not received.
     ///
-    /// This field is set manually by userspace after a subvolume is received.
+    /// This field is set manually by userspace after a subvolume is received,
+    /// which [`copy_received_metadata`](crate::copy_received_metadata) does.
     pub fn received_uuid(&self) -> Option<Uuid> {
         let ret = Uuid::from_bytes(self.0.received_uuid);
         if ret.is_nil() {
@@ -120,6 +185,34 @@ impl SubvolumeInfo {
         }
     }
 
+    /// Returns the [`uuid`], [`parent_uuid`] and [`received_uuid`] of this
+    /// subvolume together, which place it in the snapshot and send/receive
+    /// relationships of the filesystem.
+    ///
+    /// [`uuid`]: Self::uuid
+    /// [`parent_uuid`]: Self::parent_uuid
+    /// [`received_uuid`]: Self::received_uuid
+    pub fn lineage(&self) -> Lineage {
+        Lineage {
+            uuid: self.uuid(),
+            parent_uuid: self.parent_uuid(),
+            received_uuid: self.received_uuid(),
+        }
+    }
+
+    /// Returns the received metadata of this subvolume, or [`None`] if this
+    /// subvolume was not received, i.e. it has no [`received_uuid`].
+    ///
+    /// [`received_uuid`]: Self::received_uuid
+    pub fn received_info(&self) -> Option<ReceivedInfo> {
+        Some(ReceivedInfo {
+            uuid: self.received_uuid()?,
+            stransid: self.0.stransid,
+            rtransid: self.0.rtransid,
+            rtime: Timespec(self.0.rtime).into(),
+        })
+    }
+
     /// Returns the transaction ID of the subvolume root.
     pub fn generation(&self) -> u64 {
         self.0.generation
@@ -233,6 +326,250 @@ impl SubvolumeInfo {
             Some(Timespec(self.0.ctime).into())
         }
     }
+
+    /// Returns all timestamps exactly as stored, without converting them to
+    /// [`SystemTime`].
+    pub fn raw_timespecs(&self) -> RawTimespecs {
+        RawTimespecs {
+            ctime: (self.0.ctime.tv_sec, self.0.ctime.tv_nsec),
+            otime: (self.0.otime.tv_sec, self.0.otime.tv_nsec),
+            stime: (self.0.stime.tv_sec, self.0.stime.tv_nsec),
+            rtime: (self.0.rtime.tv_sec, self.0.rtime.tv_nsec),
+        }
+    }
+
+    /// Returns whether this subvolume was created before `other`, which must be
+    /// on the same filesystem.
+    ///
+    /// This compares the transaction IDs when the subvolumes were created
+    /// ([`otransid`]), which only ever increase, falling back to the IDs for
+    /// subvolumes created in the same transaction. Unlike [`created`], it is
+    /// not affected by adjustments of the system clock.
+    ///
+    /// [`otransid`]: Self::otransid
+    /// [`created`]: Self::created
+    pub fn created_before(&self, other: &SubvolumeInfo) -> bool {
+        (self.otransid(), self.id()) < (other.otransid(), other.id())
+    }
+
+    /// Returns every field, starting with the creation order of
+    /// [`created_before`](Self::created_before), for comparisons.
+    fn fields(&self) -> impl Ord + '_ {
+        let info = &self.0;
+        let time = |t: &ffi::timespec| (t.tv_sec, t.tv_nsec);
+        (
+            (info.otransid, info.id),
+            (info.parent_id, info.dir_id, info.flags, info.generation),
+            (&info.uuid, &info.parent_uuid, &info.received_uuid),
+            (info.ctransid, info.stransid, info.rtransid),
+            [&info.ctime, &info.otime, &info.stime, &info.rtime].map(time),
+        )
+    }
+
+    /// Returns whether this subvolume is a snapshot of `other`.
+    pub fn is_snapshot_of(&self, other: &SubvolumeInfo) -> bool {
+        self.parent_uuid() == Some(other.uuid())
+    }
+
+    /// Returns whether this subvolume and `other` are snapshots of the same
+    /// subvolume.
+    pub fn shares_source_with(&self, other: &SubvolumeInfo) -> bool {
+        self.parent_uuid().is_some() && self.parent_uuid() == other.parent_uuid()
+    }
+
+    /// Returns whether this subvolume was received from `source`, i.e. its
+    /// [`received_uuid`] is the source's [`uuid`] and its [`stransid`] is the
+    /// source's [`ctransid`].
+    ///
+    /// [`received_uuid`]: Self::received_uuid
+    /// [`uuid`]: Self::uuid
+    /// [`stransid`]: Self::stransid
+    /// [`ctransid`]: Self::ctransid
+    pub fn matches_received(&self, source: &SubvolumeInfo) -> bool {
+        self.received_uuid() == Some(source.uuid())
+            && self.stransid().map(NonZeroU64::get) == Some(source.ctransid())
+    }
+}
+
+/// A timestamp formatted as RFC 3339 in UTC, e.g. `2023-11-14T22:13:20Z`.
+struct Rfc3339(i64, i64);
+
+impl fmt::Debug for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = self.0.div_euclid(86400);
+        let secs = self.0.rem_euclid(86400);
+
+        // Civil date from days since Unix Epoch, from Howard Hinnant's
+        // chrono-compatible low-level date algorithms.
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        )?;
+        if self.1 != 0 {
+            write!(f, ".{:09}", self.1)?;
+        }
+        f.write_str("Z")
+    }
+}
+
+/// Formats UUIDs as hyphenated strings and timestamps as RFC 3339, unlike the
+/// raw struct returned by [`as_raw`](SubvolumeInfo::as_raw).
+impl fmt::Debug for SubvolumeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SubvolumeInfo")
+            .field("id", &self.id())
+            .field("parent_id", &self.parent_id())
+            .field("dir_id", &self.dir_id())
+            .field("flags", &format_args!("{:#x}", self.flags()))
+            .field("uuid", &self.uuid())
+            .field("parent_uuid", &self.parent_uuid())
+            .field("received_uuid", &self.received_uuid())
+            .field("generation", &self.generation())
+            .field("ctransid", &self.ctransid())
+            .field("otransid", &self.otransid())
+            .field("stransid", &self.stransid())
+            .field("rtransid", &self.rtransid())
+            .field("ctime", &Rfc3339(self.0.ctime.tv_sec, self.0.ctime.tv_nsec))
+            .field("otime", &Rfc3339(self.0.otime.tv_sec, self.0.otime.tv_nsec))
+            .field("stime", &Rfc3339(self.0.stime.tv_sec, self.0.stime.tv_nsec))
+            .field("rtime", &Rfc3339(self.0.rtime.tv_sec, self.0.rtime.tv_nsec))
+            .finish()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for SubvolumeInfo {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("SubvolumeInfo", 20)?;
+        state.serialize_field("id", &self.id())?;
+        state.serialize_field("parent_id", &self.parent_id().map(NonZeroU64::get))?;
+        state.serialize_field("dir_id", &self.dir_id().map(NonZeroU64::get))?;
+        state.serialize_field("flags", &self.flags())?;
+        state.serialize_field("uuid", &self.uuid())?;
+        state.serialize_field("parent_uuid", &self.parent_uuid())?;
+        state.serialize_field("received_uuid", &self.received_uuid())?;
+        state.serialize_field("generation", &self.generation())?;
+        state.serialize_field("ctransid", &self.ctransid())?;
+        state.serialize_field("otransid", &self.otransid())?;
+        state.serialize_field("stransid", &self.stransid().map(NonZeroU64::get))?;
+        state.serialize_field("rtransid", &self.rtransid().map(NonZeroU64::get))?;
+        state.serialize_field("ctime", &self.0.ctime.tv_sec)?;
+        state.serialize_field("ctime_nsec", &self.0.ctime.tv_nsec)?;
+        state.serialize_field("otime", &self.0.otime.tv_sec)?;
+        state.serialize_field("otime_nsec", &self.0.otime.tv_nsec)?;
+        state.serialize_field("stime", &self.0.stime.tv_sec)?;
+        state.serialize_field("stime_nsec", &self.0.stime.tv_nsec)?;
+        state.serialize_field("rtime", &self.0.rtime.tv_sec)?;
+        state.serialize_field("rtime_nsec", &self.0.rtime.tv_nsec)?;
+        state.end()
+    }
+}
+
+/// Subvolume information is ordered by creation, comparing [`otransid`] and
+/// then [`id`], as in [`created_before`]. Values describing the same
+/// subvolume, e.g. fetched at different times, are ordered by their other
+/// fields, so two values are only equal if all their fields are. The order is
+/// only meaningful for subvolumes on the same filesystem.
+///
+/// [`otransid`]: SubvolumeInfo::otransid
+/// [`id`]: SubvolumeInfo::id
+/// [`created_before`]: SubvolumeInfo::created_before
+impl Ord for SubvolumeInfo {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.fields().cmp(&other.fields())
+    }
+}
+
+impl PartialOrd for SubvolumeInfo {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SubvolumeInfo {
+    fn eq(&self, other: &Self) -> bool {
+        self.fields() == other.fields()
+    }
+}
+
+impl Eq for SubvolumeInfo {}
+
+/// Formats the subvolume information in a single line by default, e.g.:
+///
+/// ```text
+/// ID 256 gen 10 parent 5 uuid 0d5a6bb1-7a4b-4b6e-9c5b-1b8d1d9e6a6e
+/// ```
+///
+/// The alternate form (`{:#}`) lists every field on its own line, e.g.:
+///
+/// ```text
+/// ID: 256
+/// Parent ID: 5
+/// Dir ID: 256
+/// Flags: 0x0
+/// UUID: 0d5a6bb1-7a4b-4b6e-9c5b-1b8d1d9e6a6e
+/// Parent UUID: -
+/// Received UUID: -
+/// Generation: 10
+/// Created at transaction: 9
+/// Changed at transaction: 10
+/// Created: 1700000000.123456789
+/// Changed: 1700000100.000000000
+/// ```
+impl fmt::Display for SubvolumeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parent_id = self.parent_id().map_or(0, NonZeroU64::get);
+        if !f.alternate() {
+            return write!(
+                f,
+                "ID {} gen {} parent {} uuid {}",
+                self.id(),
+                self.generation(),
+                parent_id,
+                self.uuid()
+            );
+        }
+
+        fn uuid_or_dash(f: &mut fmt::Formatter<'_>, uuid: Option<Uuid>) -> fmt::Result {
+            match uuid {
+                Some(uuid) => writeln!(f, "{}", uuid),
+                None => writeln!(f, "-"),
+            }
+        }
+
+        writeln!(f, "ID: {}", self.id())?;
+        writeln!(f, "Parent ID: {}", parent_id)?;
+        writeln!(f, "Dir ID: {}", self.dir_id().map_or(0, NonZeroU64::get))?;
+        writeln!(f, "Flags: {:#x}", self.flags())?;
+        writeln!(f, "UUID: {}", self.uuid())?;
+        write!(f, "Parent UUID: ")?;
+        uuid_or_dash(f, self.parent_uuid())?;
+        write!(f, "Received UUID: ")?;
+        uuid_or_dash(f, self.received_uuid())?;
+        writeln!(f, "Generation: {}", self.generation())?;
+        writeln!(f, "Created at transaction: {}", self.otransid())?;
+        writeln!(f, "Changed at transaction: {}", self.ctransid())?;
+        writeln!(f, "Created: {}.{:09}", self.otime(), self.otime_nsec())?;
+        write!(f, "Changed: {}.{:09}", self.ctime(), self.ctime_nsec())
+    }
 }
 
 impl Default for SubvolumeInfo {
@@ -241,24 +578,57 @@ impl Default for SubvolumeInfo {
     }
 }
 
-pub struct SubvolumeIdIterator(*mut ffi::btrfs_util_subvolume_iterator);
+/// An iterator over subvolume IDs
+pub struct SubvolumeIdIterator {
+    iter: *mut ffi::btrfs_util_subvolume_iterator,
+    /// The file descriptor the iterator was created from, if owned, kept open
+    /// for as long as the iterator uses it.
+    fd: Option<OwnedFd>,
+    /// Prepended to the returned paths.
+    prefix: Option<PathBuf>,
+    /// Only paths starting with this are returned.
+    filter: Option<PathBuf>,
+    /// Only subvolumes with at least this ID are returned.
+    min_id: u64,
+    /// The number of items left to yield, if limited.
+    remaining: Option<usize>,
+}
+
+/// A predicate on subvolume info, see [`IterateSubvolume::with_info_filter`].
+type InfoFilter = Arc<dyn Fn(&SubvolumeInfo) -> bool + Send + Sync>;
 
 /// A builder to create a subvolume iterator
 pub struct IterateSubvolume {
     path: CString,
     top: u64,
     post_order: bool,
+    root_relative_paths: bool,
+    filter: Option<PathBuf>,
+    min_id: u64,
+    info_filter: Option<InfoFilter>,
+    limit: Option<usize>,
 }
 
 impl IterateSubvolume {
     /// Path in a Btrfs filesystem. This may be any path in the filesystem; it
     /// does not have to refer to a subvolume unless `top` is not provided.
     /// If `top` is not provided, the subvolume ID of `path` is used.
+    ///
+    /// Iterating requires appropriate privilege (`CAP_SYS_ADMIN`) if `top` is
+    /// provided. Otherwise, unprivileged users can iterate on kernels
+    /// supporting `BTRFS_IOC_GET_SUBVOL_ROOTREF` and `BTRFS_IOC_INO_LOOKUP_USER`
+    /// (kernel >= 4.18); subvolumes beneath directories the user cannot access
+    /// are skipped.
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
         Self {
             path: CString::new(path.as_ref().as_os_str().as_bytes()).unwrap(),
             top: 0,
             post_order: false,
+            root_relative_paths: false,
+            filter: None,
+            min_id: 0,
+            info_filter: None,
+            limit: None,
         }
     }
 
@@ -276,7 +646,8 @@ impl IterateSubvolume {
         self
     }
 
-    /// Use post order traversal
+    /// Use post order traversal. Every subvolume is yielded after all of its
+    /// descendants, so the subvolumes can be deleted in the yielded order.
     pub fn post_order(&mut self) -> &mut Self {
         self.post_order = true;
         self
@@ -288,8 +659,77 @@ impl IterateSubvolume {
         self
     }
 
-    /// Returns an iterator to iterate over subvolume IDs
-    pub fn iter_with_id(&self) -> Result<SubvolumeIdIterator, Error> {
+    /// Return paths relative to the filesystem root instead of to `top`.
+    ///
+    /// The path of `top` is looked up once when creating the iterator, which
+    /// requires appropriate privilege (`CAP_SYS_ADMIN`), and is joined with the
+    /// path of every subvolume, whatever the traversal order.
+    pub fn root_relative_paths(&mut self) -> &mut Self {
+        self.root_relative_paths = true;
+        self
+    }
+
+    /// Only yield subvolumes whose path, as returned by the iterator, starts
+    /// with `prefix`. Paths are compared by whole components, so `a/b` matches
+    /// `a/b/c` but not `a/bc`.
+    ///
+    /// libbtrfsutil cannot skip subtrees, so every subvolume beneath `top` is
+    /// still visited; the filtering only saves allocating the results.
+    pub fn with_prefix<P: AsRef<Path>>(&mut self, prefix: P) -> &mut Self {
+        self.filter = Some(prefix.as_ref().to_path_buf());
+        self
+    }
+
+    /// Only yield subvolumes whose info satisfies `pred`, which is called as
+    /// each subvolume is fetched. Errors are always yielded.
+    ///
+    /// This only applies when iterating over subvolume info, i.e. not to
+    /// [`iter_with_id`] and [`for_each_id`].
+    ///
+    /// [`iter_with_id`]: Self::iter_with_id
+    /// [`for_each_id`]: Self::for_each_id
+    pub fn with_info_filter<F>(&mut self, pred: F) -> &mut Self
+    where
+        F: Fn(&SubvolumeInfo) -> bool + Send + Sync + 'static,
+    {
+        self.info_filter = Some(Arc::new(pred));
+        self
+    }
+
+    /// Only yield subvolumes with an ID of at least `id`. Btrfs allocates
+    /// subvolume IDs in increasing order and never reuses them.
+    ///
+    /// libbtrfsutil cannot seek, so the skipped subvolumes are still visited.
+    pub fn min_id(&mut self, id: u64) -> &mut Self {
+        self.min_id = id;
+        self
+    }
+
+    /// Stop after yielding `n` items, including errors. The libbtrfsutil
+    /// iterator is destroyed and its file descriptor closed as soon as the
+    /// `n`th item is yielded, rather than when the iterator is dropped.
+    ///
+    /// Together with [`min_id`], this lists subvolumes a page at a time: each
+    /// page starts at one more than the largest ID of the previous page. This
+    /// yields every subvolume once if the IDs come in increasing order, which
+    /// is the case for subvolumes directly beneath `top`, as libbtrfsutil
+    /// lists the children of a subvolume in increasing order of ID. Nested
+    /// subvolumes come right after their parent instead, so pages could skip
+    /// them; a nested layout can be paged by the position in the listing
+    /// with [`Iterator::skip`] instead, at the cost of visiting the skipped
+    /// subvolumes again.
+    ///
+    /// This does not apply to [`for_each_id`](Self::for_each_id), which can
+    /// stop by itself.
+    ///
+    /// [`min_id`]: Self::min_id
+    pub fn limit(&mut self, n: usize) -> &mut Self {
+        self.limit = Some(n);
+        self
+    }
+
+    /// Creates the libbtrfsutil iterator, without path prefix or filter.
+    fn create_iter(&self) -> Result<SubvolumeIdIterator, Error> {
         let mut flags: c_int = 0;
         if self.post_order {
             flags |= ffi::BTRFS_UTIL_SUBVOLUME_ITERATOR_POST_ORDER as c_int;
@@ -304,15 +744,113 @@ impl IterateSubvolume {
                 &mut iter,
             );
             if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
-                return Err(Error::new(errcode));
+                let path = OsStr::from_bytes(self.path.as_bytes());
+                return Err(Error::new(errcode).with_context(path));
+            }
+        }
+        Ok(SubvolumeIdIterator {
+            iter,
+            fd: None,
+            prefix: None,
+            filter: None,
+            min_id: 0,
+            remaining: None,
+        })
+    }
+
+    /// Returns an iterator to iterate over subvolume IDs. If the iterator
+    /// cannot be created, the error carries the path (see [`Error::context`]).
+    pub fn iter_with_id(&self) -> Result<SubvolumeIdIterator, Error> {
+        let mut ret = self.create_iter()?;
+        ret.filter = self.filter.clone();
+        ret.min_id = self.min_id;
+        ret.remaining = self.limit;
+        if self.root_relative_paths && self.top != FS_TREE_OBJECTID {
+            let path = OsStr::from_bytes(self.path.as_bytes());
+            ret.prefix = Some(subvolume_path_with_id(path, self.top)?);
+        }
+        Ok(ret)
+    }
+
+    /// Calls `f` with the path and ID of each subvolume without allocating,
+    /// until it returns [`ControlFlow::Break`]. The path is only valid for the
+    /// duration of the call.
+    ///
+    /// The paths are always relative to `top`, as returned by libbtrfsutil:
+    /// [`root_relative_paths`] and [`with_prefix`] do not apply.
+    ///
+    /// [`root_relative_paths`]: Self::root_relative_paths
+    /// [`with_prefix`]: Self::with_prefix
+    pub fn for_each_id<F>(&self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(&CStr, u64) -> ControlFlow<()>,
+    {
+        let iter = self.create_iter()?;
+        loop {
+            let mut path_ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
+            let mut id: u64 = 0;
+            let errcode = unsafe {
+                ffi::btrfs_util_subvolume_iterator_next(iter.iter, &mut path_ptr, &mut id)
+            };
+            match errcode {
+                ffi::btrfs_util_error::BTRFS_UTIL_OK => {
+                    if id < self.min_id {
+                        unsafe { libc::free(path_ptr as *mut libc::c_void) };
+                        continue;
+                    }
+                    let flow = f(unsafe { CStr::from_ptr(path_ptr) }, id);
+                    unsafe { libc::free(path_ptr as *mut libc::c_void) };
+                    if flow.is_break() {
+                        return Ok(());
+                    }
+                }
+                ffi::btrfs_util_error::BTRFS_UTIL_ERROR_STOP_ITERATION => return Ok(()),
+                _ => return Err(Error::new(errcode)),
             }
         }
-        Ok(SubvolumeIdIterator(iter))
+    }
+
+    /// Lists the subvolumes beneath the subvolume at `mount`, with their paths
+    /// joined to `mount`, sorted by path.
+    ///
+    /// Paths are compared by components, so every subvolume comes right
+    /// before its descendants, as in pre-order traversal, and siblings are in
+    /// a deterministic order.
+    pub fn flat_paths<P: AsRef<Path>>(mount: P) -> Result<Vec<(PathBuf, SubvolumeInfo)>, Error> {
+        let mount = mount.as_ref();
+        let mut ret = IterateSubvolume::new(mount)
+            .iter_with_info()?
+            .map(|item| item.map(|(path, info)| (mount.join(path), info)))
+            .collect::<Result<Vec<_>, _>>()?;
+        ret.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(ret)
     }
 
     /// Returns an iterator to iterate over subvolume info
     pub fn iter_with_info(&self) -> Result<SubvolumeInfoIterator, Error> {
-        Ok(self.iter_with_id()?.into())
+        Ok(SubvolumeInfoIterator(
+            self.iter_with_id()?,
+            self.info_filter.clone(),
+        ))
+    }
+
+    /// Returns an iterator to iterate over subvolume info, like
+    /// [`iter_with_info`], but merging the iterator creation error into the
+    /// items: if the creation fails, the error is yielded as the first and only
+    /// item.
+    ///
+    /// [`iter_with_info`]: Self::iter_with_info
+    pub fn iter(&self) -> SubvolumeIter {
+        match self.iter_with_info() {
+            Ok(iter) => SubvolumeIter {
+                iter: Some(iter),
+                err: None,
+            },
+            Err(err) => SubvolumeIter {
+                iter: None,
+                err: Some(err),
+            },
+        }
     }
 }
 
@@ -326,30 +864,144 @@ unsafe fn c_char_ptr_to_path(ptr: *mut std::os::raw::c_char) -> PathBuf {
     ret
 }
 
+impl SubvolumeIdIterator {
+    /// Creates an iterator over subvolumes beneath the subvolume with ID `top`
+    /// on the filesystem containing `fd`. If `top` is zero, the subvolume
+    /// containing `fd` is used.
+    pub(crate) fn with_fd(fd: OwnedFd, top: u64, flags: c_int) -> Result<Self, Error> {
+        let mut iter: *mut ffi::btrfs_util_subvolume_iterator = ptr::null_mut();
+        unsafe {
+            let errcode =
+                ffi::btrfs_util_create_subvolume_iterator_fd(fd.as_raw_fd(), top, flags, &mut iter);
+            if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+                return Err(Error::new(errcode));
+            }
+        }
+        Ok(SubvolumeIdIterator {
+            iter,
+            fd: Some(fd),
+            prefix: None,
+            filter: None,
+            min_id: 0,
+            remaining: None,
+        })
+    }
+
+    /// Creates an iterator from a raw libbtrfsutil iterator, taking ownership
+    /// of it.
+    ///
+    /// # Safety
+    ///
+    /// `iter` must be null or a valid iterator returned by libbtrfsutil which
+    /// is not owned by anything else. A null iterator must not be advanced.
+    pub unsafe fn from_raw(iter: *mut ffi::btrfs_util_subvolume_iterator) -> Self {
+        SubvolumeIdIterator {
+            iter,
+            fd: None,
+            prefix: None,
+            filter: None,
+            min_id: 0,
+            remaining: None,
+        }
+    }
+
+    /// Consumes the iterator, returning the raw libbtrfsutil iterator. The
+    /// caller is responsible for destroying it with
+    /// `btrfs_util_destroy_subvolume_iterator`.
+    ///
+    /// If the iterator owns its file descriptor, the descriptor is leaked so
+    /// that the raw iterator remains usable.
+    pub fn into_raw(mut self) -> *mut ffi::btrfs_util_subvolume_iterator {
+        if let Some(fd) = self.fd.take() {
+            let _ = fd.into_raw_fd();
+        }
+        std::mem::replace(&mut self.iter, ptr::null_mut())
+    }
+
+    /// Returns whether the limit was reached.
+    fn exhausted(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    /// Counts a yielded item towards the limit, destroying the libbtrfsutil
+    /// iterator once it is reached.
+    fn count_item(&mut self) {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+            if *remaining == 0 {
+                self.close();
+            }
+        }
+    }
+
+    /// Destroys the libbtrfsutil iterator and closes the file descriptor.
+    fn close(&mut self) {
+        if !self.iter.is_null() {
+            unsafe {
+                ffi::btrfs_util_destroy_subvolume_iterator(self.iter);
+            }
+            self.iter = ptr::null_mut();
+        }
+        // The iterator may still use the file descriptor until destroyed.
+        drop(self.fd.take());
+    }
+
+    /// Returns whether the subvolume passes the filters.
+    fn matches(&self, path: &Path, id: u64) -> bool {
+        if id < self.min_id {
+            return false;
+        }
+        match &self.filter {
+            Some(filter) => path.starts_with(filter),
+            None => true,
+        }
+    }
+
+    /// The given pointer will be freed
+    unsafe fn take_path(&self, ptr: *mut std::os::raw::c_char) -> PathBuf {
+        let path = c_char_ptr_to_path(ptr);
+        match &self.prefix {
+            Some(prefix) => prefix.join(path),
+            None => path,
+        }
+    }
+}
+
 impl Iterator for SubvolumeIdIterator {
     type Item = Result<(PathBuf, NonZeroU64), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut path_ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
-        let mut id: u64 = 0;
-        let errcode =
-            unsafe { ffi::btrfs_util_subvolume_iterator_next(self.0, &mut path_ptr, &mut id) };
-        match errcode {
-            ffi::btrfs_util_error::BTRFS_UTIL_OK => {
-                let path = unsafe { c_char_ptr_to_path(path_ptr) };
-                Some(Ok((path, NonZeroU64::new(id).unwrap())))
-            }
-            ffi::btrfs_util_error::BTRFS_UTIL_ERROR_STOP_ITERATION => None,
-            _ => Some(Err(Error::new(errcode))),
+        if self.exhausted() {
+            return None;
         }
+        let item = loop {
+            let mut path_ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
+            let mut id: u64 = 0;
+            let errcode = unsafe {
+                ffi::btrfs_util_subvolume_iterator_next(self.iter, &mut path_ptr, &mut id)
+            };
+            break match errcode {
+                ffi::btrfs_util_error::BTRFS_UTIL_OK => {
+                    let path = unsafe { self.take_path(path_ptr) };
+                    if !self.matches(&path, id) {
+                        continue;
+                    }
+                    Some(Ok((path, NonZeroU64::new(id).unwrap())))
+                }
+                ffi::btrfs_util_error::BTRFS_UTIL_ERROR_STOP_ITERATION => None,
+                _ => Some(Err(Error::new(errcode))),
+            };
+        };
+        if item.is_some() {
+            self.count_item();
+        }
+        item
     }
 }
 
 impl Drop for SubvolumeIdIterator {
     fn drop(&mut self) {
-        unsafe {
-            ffi::btrfs_util_destroy_subvolume_iterator(self.0);
-        }
+        self.close();
     }
 }
 
@@ -361,32 +1013,292 @@ impl From<SubvolumeInfoIterator> for SubvolumeIdIterator {
 
 impl From<SubvolumeIdIterator> for SubvolumeInfoIterator {
     fn from(iter: SubvolumeIdIterator) -> Self {
-        Self(iter)
+        Self(iter, None)
     }
 }
 
-pub struct SubvolumeInfoIterator(SubvolumeIdIterator);
+pub struct SubvolumeInfoIterator(SubvolumeIdIterator, Option<InfoFilter>);
 
 impl Iterator for SubvolumeInfoIterator {
     type Item = Result<(PathBuf, SubvolumeInfo), Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut path_ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
-        let mut info = SubvolumeInfo::new();
-        let errcode = unsafe {
-            ffi::btrfs_util_subvolume_iterator_next_info(self.0 .0, &mut path_ptr, &mut info.0)
+        if self.0.exhausted() {
+            return None;
+        }
+        let item = loop {
+            let mut path_ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
+            let mut info = SubvolumeInfo::new();
+            let errcode = unsafe {
+                ffi::btrfs_util_subvolume_iterator_next_info(
+                    self.0.iter,
+                    &mut path_ptr,
+                    &mut info.0,
+                )
+            };
+            break match errcode {
+                ffi::btrfs_util_error::BTRFS_UTIL_OK => {
+                    let path = unsafe { self.0.take_path(path_ptr) };
+                    if !self.0.matches(&path, info.id()) {
+                        continue;
+                    }
+                    if let Some(filter) = &self.1 {
+                        if !filter(&info) {
+                            continue;
+                        }
+                    }
+                    Some(Ok((path, info)))
+                }
+                ffi::btrfs_util_error::BTRFS_UTIL_ERROR_STOP_ITERATION => None,
+                _ => Some(Err(Error::new(errcode))),
+            };
         };
-        match errcode {
-            ffi::btrfs_util_error::BTRFS_UTIL_OK => {
-                let path = unsafe { c_char_ptr_to_path(path_ptr) };
-                Some(Ok((path, info)))
-            }
-            ffi::btrfs_util_error::BTRFS_UTIL_ERROR_STOP_ITERATION => None,
-            _ => Some(Err(Error::new(errcode))),
+        if item.is_some() {
+            self.0.count_item();
         }
+        item
     }
 }
 
+/// An iterator over subvolume info which also yields the iterator creation
+/// error. See [`IterateSubvolume::iter`].
+pub struct SubvolumeIter {
+    iter: Option<SubvolumeInfoIterator>,
+    err: Option<Error>,
+}
+
+impl Iterator for SubvolumeIter {
+    type Item = Result<(PathBuf, SubvolumeInfo), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.err.take() {
+            return Some(Err(err));
+        }
+        self.iter.as_mut()?.next()
+    }
+}
+
+/// A source of subvolumes to iterate over.
+///
+/// Code selecting subvolumes can be generic over this trait to be tested
+/// without a Btrfs filesystem, using `FakeSubvolumes` from the `test-util`
+/// feature.
+pub trait SubvolumeSource {
+    type Iter: Iterator<Item = Result<(PathBuf, SubvolumeInfo), Error>>;
+
+    /// Returns an iterator over the subvolumes and their paths.
+    fn subvolumes(&self) -> Self::Iter;
+}
+
+impl SubvolumeSource for IterateSubvolume {
+    type Iter = SubvolumeIter;
+
+    fn subvolumes(&self) -> Self::Iter {
+        self.iter()
+    }
+}
+
+/// Lists all subvolumes beneath the root of the filesystem containing the
+/// `path` in a single pass, partitioned into originals and snapshots (i.e.,
+/// subvolumes with a [`parent_uuid`]), in that order.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+///
+/// [`parent_uuid`]: SubvolumeInfo::parent_uuid
+pub fn classify_subvolumes<P: AsRef<Path>>(
+    path: P,
+) -> Result<(Vec<SubvolumeInfo>, Vec<SubvolumeInfo>), Error> {
+    let mut originals = Vec::new();
+    let mut snapshots = Vec::new();
+    for item in IterateSubvolume::new(path).all().iter_with_info()? {
+        let (_, info) = item?;
+        if info.parent_uuid().is_some() {
+            snapshots.push(info);
+        } else {
+            originals.push(info);
+        }
+    }
+    Ok((originals, snapshots))
+}
+
+/// Lists whether each subvolume beneath the root of the filesystem containing
+/// the `path` is read-only, keyed by subvolume ID, in a single pass.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn read_only_map<P: AsRef<Path>>(path: P) -> Result<HashMap<u64, bool>, Error> {
+    let mut map = HashMap::new();
+    for item in IterateSubvolume::new(path).all().iter_with_info()? {
+        let (_, info) = item?;
+        map.insert(info.id(), info.is_read_only());
+    }
+    Ok(map)
+}
+
+/// The result of [`subvolumes_parallel`]: the subvolumes whose information
+/// was fetched, and the subvolumes whose information could not be.
+#[derive(Debug, Default)]
+pub struct ParallelSubvolumes {
+    /// The paths and information of the subvolumes which were fetched.
+    pub subvolumes: Vec<(PathBuf, SubvolumeInfo)>,
+    /// The ID of each subvolume which could not be fetched, with its error.
+    pub errors: Vec<(u64, Error)>,
+}
+
+/// Lists all subvolumes beneath the root of the filesystem containing the
+/// `path`, enumerating their IDs with a single iterator, then fetching their
+/// information on `threads` threads (at least one, and no more than there are
+/// subvolumes). The returned paths are relative to the filesystem root.
+///
+/// A failure to fetch the information of one subvolume does not fail the
+/// whole listing: it is collected in [`ParallelSubvolumes::errors`] alongside
+/// the subvolumes which were fetched. Only a failure to enumerate the IDs is
+/// returned as an error. Both lists are in no particular order; sort them if
+/// needed. Subvolumes deleted between the enumeration and the fetch are
+/// skipped.
+///
+/// A panic in a worker thread is propagated to the caller.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn subvolumes_parallel<P: AsRef<Path>>(
+    path: P,
+    threads: usize,
+) -> Result<ParallelSubvolumes, Error> {
+    let path = path.as_ref();
+    let ids = IterateSubvolume::new(path)
+        .all()
+        .iter_with_id()?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let next = AtomicUsize::new(0);
+    let results = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.max(1).min(ids.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, atomic::Ordering::Relaxed);
+                        let Some((subvol_path, id)) = ids.get(index) else {
+                            return results;
+                        };
+                        let result = subvolume_info_with_id(path, id.get());
+                        results.push((subvol_path, id.get(), result));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|payload| panic::resume_unwind(payload))
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut ret = ParallelSubvolumes {
+        subvolumes: Vec::with_capacity(results.len()),
+        errors: Vec::new(),
+    };
+    for (subvol_path, id, result) in results {
+        match result {
+            Ok(info) => ret.subvolumes.push((subvol_path.clone(), info)),
+            Err(err) if err.kind() == ErrorKind::SUBVOLUME_NOT_FOUND => {}
+            Err(err) => ret.errors.push((id, err)),
+        }
+    }
+    Ok(ret)
+}
+
+/// Iterates over the subvolumes beneath each of the `tops` on the filesystem
+/// containing the `path`, grouped by top in the given order. A subvolume
+/// beneath more than one top, e.g. because one top is beneath another, is only
+/// yielded the first time, as identified by its ID.
+///
+/// The returned paths are relative to the filesystem root, as with
+/// [`IterateSubvolume::root_relative_paths`], so that they are unambiguous.
+/// All the iterators are created up front, returning the first error.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn iter_subvolumes_under<P: AsRef<Path>>(
+    path: P,
+    tops: &[u64],
+) -> Result<impl Iterator<Item = Result<(PathBuf, SubvolumeInfo), Error>>, Error> {
+    let iters = tops
+        .iter()
+        .map(|&top| {
+            IterateSubvolume::new(path.as_ref())
+                .top(top)
+                .root_relative_paths()
+                .iter_with_info()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut seen = HashSet::new();
+    Ok(iters.into_iter().flatten().filter(move |item| match item {
+        Ok((_, info)) => seen.insert(info.id()),
+        Err(_) => true,
+    }))
+}
+
+/// Walks all subvolumes beneath the root of the filesystem containing the
+/// `path` in pre-order, calling `visitor` with the path of each subvolume
+/// relative to the filesystem root, its information, and the information of
+/// its parent subvolume.
+///
+/// Pre-order guarantees that a parent is visited before its children, so
+/// only the ancestors of the current subvolume are kept in memory rather than
+/// the whole tree. The parent is [`None`] if it is not visited, i.e. for the
+/// subvolumes directly beneath the filesystem root, which is not itself
+/// visited, and for subvolumes whose parent could not be found among their
+/// visited ancestors.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn walk_subvolumes<P, F>(path: P, mut visitor: F) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    F: FnMut(&Path, &SubvolumeInfo, Option<&SubvolumeInfo>),
+{
+    let mut ancestors: Vec<SubvolumeInfo> = Vec::new();
+    for item in IterateSubvolume::new(path)
+        .all()
+        .pre_order()
+        .iter_with_info()?
+    {
+        let (path, info) = item?;
+        let parent_id = info.parent_id().map(NonZeroU64::get);
+        while ancestors
+            .last()
+            .is_some_and(|ancestor| Some(ancestor.id()) != parent_id)
+        {
+            ancestors.pop();
+        }
+        visitor(&path, &info, ancestors.last());
+        ancestors.push(info);
+    }
+    Ok(())
+}
+
+/// Finds the subvolume with the given `uuid` on the filesystem containing the
+/// `path` and returns its path relative to the filesystem root, or [`None`]
+/// if no subvolume has that UUID.
+///
+/// This enumerates all subvolumes, so it takes time proportional to their
+/// number.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn subvolume_path_by_uuid<P: AsRef<Path>>(
+    path: P,
+    uuid: Uuid,
+) -> Result<Option<PathBuf>, Error> {
+    for item in IterateSubvolume::new(path).all().iter_with_info()? {
+        let (path, info) = item?;
+        if info.uuid() == uuid {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
 /// Gets the path of the subvolume relative to the filesystem root.
 ///
 /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
@@ -396,6 +1308,8 @@ pub fn subvolume_path<P: AsRef<Path>>(path: P) -> Result<PathBuf, Error> {
 }
 
 /// Gets the path of the subvolume with a given ID relative to the filesystem root.
+/// On failure, the error carries the `path` and `id` (see [`Error::context`]
+/// and [`Error::subvolume_id`]).
 ///
 /// This requires appropriate privilege (`CAP_SYS_ADMIN`).
 pub fn subvolume_path_with_id<P: AsRef<Path>>(path: P, id: u64) -> Result<PathBuf, Error> {
@@ -404,10 +1318,257 @@ pub fn subvolume_path_with_id<P: AsRef<Path>>(path: P, id: u64) -> Result<PathBu
     unsafe {
         let errcode = ffi::btrfs_util_subvolume_path(cpath.as_ptr(), id, &mut ret_path_ptr);
         if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
-            return Err(Error::new(errcode));
+            return Err(Error::new(errcode).with_context(path).with_subvolume_id(id));
         }
         let path = c_char_ptr_to_path(ret_path_ptr);
 
         Ok(path)
     }
 }
+
+/// Gets the path of the subvolume with a given ID relative to the filesystem
+/// root, on the filesystem containing the file descriptor `fd`. If `id` is
+/// zero, the subvolume containing `fd` is used.
+///
+/// An `O_PATH` descriptor is reopened read-only through `/proc/self/fd`,
+/// which needs procfs to be mounted and read permission on the file, failing
+/// with [`OPEN_FAILED`](ErrorKind::OPEN_FAILED) otherwise.
+///
+/// This requires appropriate privilege (`CAP_SYS_ADMIN`).
+pub fn subvolume_path_fd<F: AsFd>(fd: F, id: u64) -> Result<PathBuf, Error> {
+    with_ioctl_fd(fd.as_fd(), |fd| {
+        let mut ret_path_ptr: *mut std::os::raw::c_char = std::ptr::null_mut();
+        unsafe {
+            let errcode = ffi::btrfs_util_subvolume_path_fd(fd, id, &mut ret_path_ptr);
+            if errcode != ffi::btrfs_util_error::BTRFS_UTIL_OK {
+                return Err(Error::new(errcode).with_subvolume_id(id));
+            }
+            Ok(c_char_ptr_to_path(ret_path_ptr))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ptr::NonNull,
+        time::{Duration, SystemTime},
+    };
+
+    use uuid::Uuid;
+
+    use super::Rfc3339;
+    use crate::{IterateSubvolume, SubvolumeIdIterator, SubvolumeInfo};
+
+    fn info(uuid: u8, parent_uuid: u8) -> SubvolumeInfo {
+        let mut info = SubvolumeInfo::new();
+        info.0.uuid = [uuid; 16];
+        info.0.parent_uuid = [parent_uuid; 16];
+        info
+    }
+
+    #[test]
+    fn test_created_before() {
+        let mut a = SubvolumeInfo::new();
+        a.0.id = 257;
+        a.0.otransid = 10;
+        a.0.otime.tv_sec = 2000;
+        let mut b = SubvolumeInfo::new();
+        b.0.id = 256;
+        b.0.otransid = 11;
+        b.0.otime.tv_sec = 1000;
+        assert!(a.created_before(&b));
+        assert!(!b.created_before(&a));
+        b.0.otransid = 10;
+        assert!(b.created_before(&a));
+        assert!(!a.created_before(&a));
+    }
+
+    #[test]
+    fn test_ord() {
+        let info = |otransid, id| {
+            let mut info = SubvolumeInfo::new();
+            info.0.otransid = otransid;
+            info.0.id = id;
+            info
+        };
+        let mut infos = [info(9, 256), info(7, 258), info(7, 257)];
+        infos.sort();
+        let ids: Vec<u64> = infos.iter().map(SubvolumeInfo::id).collect();
+        assert_eq!(ids, [257, 258, 256]);
+
+        let mut changed = info(9, 256);
+        changed.0.generation = 12;
+        assert_ne!(changed, info(9, 256));
+        assert!(changed > info(9, 256));
+        assert!(changed < info(9, 257));
+        assert!(!changed.created_before(&info(9, 256)));
+        assert!(!info(9, 256).created_before(&changed));
+    }
+
+    #[test]
+    fn test_is_snapshot_of() {
+        let source = info(1, 0);
+        let snapshot = info(2, 1);
+        assert!(snapshot.is_snapshot_of(&source));
+        assert!(!source.is_snapshot_of(&snapshot));
+        assert!(!source.is_snapshot_of(&source));
+    }
+
+    #[test]
+    fn test_shares_source_with() {
+        let source = info(1, 0);
+        let a = info(2, 1);
+        let b = info(3, 1);
+        let c = info(4, 2);
+        assert!(a.shares_source_with(&b));
+        assert!(!a.shares_source_with(&c));
+        assert!(!source.shares_source_with(&info(5, 0)));
+    }
+
+    #[test]
+    fn test_matches_received() {
+        let mut source = info(1, 0);
+        source.0.ctransid = 42;
+        let mut received = info(2, 0);
+        received.0.received_uuid = [1; 16];
+        received.0.stransid = 42;
+        assert!(received.matches_received(&source));
+        assert!(!source.matches_received(&received));
+
+        received.0.stransid = 41;
+        assert!(!received.matches_received(&source));
+    }
+
+    #[test]
+    fn test_lineage() {
+        let source = info(1, 0);
+        let lineage = source.lineage();
+        assert_eq!(lineage.uuid, source.uuid());
+        assert!(!lineage.is_snapshot());
+        assert!(!lineage.is_received());
+
+        let mut received = info(2, 1);
+        received.0.received_uuid = [3; 16];
+        let lineage = received.lineage();
+        assert_eq!(lineage.parent_uuid, Some(source.uuid()));
+        assert_eq!(lineage.received_uuid, Some(Uuid::from_bytes([3; 16])));
+        assert!(lineage.is_snapshot());
+        assert!(lineage.is_received());
+    }
+
+    #[test]
+    fn test_received_info() {
+        let mut info = info(1, 0);
+        assert_eq!(info.received_info(), None);
+        info.0.received_uuid = [2; 16];
+        info.0.stransid = 42;
+        info.0.rtransid = 43;
+        info.0.rtime.tv_sec = 1_700_000_000;
+        let received = info.received_info().unwrap();
+        assert_eq!(received.uuid, Uuid::from_bytes([2; 16]));
+        assert_eq!(received.stransid, 42);
+        assert_eq!(received.rtransid, 43);
+        assert_eq!(
+            received.rtime,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+        );
+    }
+
+    #[test]
+    fn test_created_before_epoch() {
+        let mut info = SubvolumeInfo::new();
+        info.0.otime.tv_sec = -2;
+        info.0.otime.tv_nsec = 500_000_000;
+        assert_eq!(
+            info.created(),
+            SystemTime::UNIX_EPOCH - Duration::from_millis(1500)
+        );
+    }
+
+    #[test]
+    fn test_raw_timespecs() {
+        let mut info = SubvolumeInfo::new();
+        info.0.otime.tv_sec = -1;
+        info.0.otime.tv_nsec = 999_999_999;
+        info.0.rtime.tv_sec = 1_700_000_000;
+        let ts = info.raw_timespecs();
+        assert_eq!(ts.ctime, (0, 0));
+        assert_eq!(ts.otime, (-1, 999_999_999));
+        assert_eq!(ts.stime, (0, 0));
+        assert_eq!(ts.rtime, (1_700_000_000, 0));
+    }
+
+    #[test]
+    fn test_display() {
+        let mut info = info(1, 0);
+        info.0.id = 256;
+        info.0.parent_id = 5;
+        info.0.generation = 10;
+        assert_eq!(
+            info.to_string(),
+            format!("ID 256 gen 10 parent 5 uuid {}", info.uuid())
+        );
+
+        let verbose = format!("{:#}", info);
+        assert_eq!(verbose.lines().count(), 12);
+        assert!(verbose.starts_with("ID: 256\nParent ID: 5\n"));
+        assert!(verbose.contains("\nParent UUID: -\n"));
+        assert!(verbose.ends_with("\nChanged: 0.000000000"));
+    }
+
+    #[test]
+    fn test_rfc3339() {
+        assert_eq!(format!("{:?}", Rfc3339(0, 0)), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            format!("{:?}", Rfc3339(1_700_000_000, 5)),
+            "2023-11-14T22:13:20.000000005Z"
+        );
+        assert_eq!(format!("{:?}", Rfc3339(-1, 0)), "1969-12-31T23:59:59Z");
+        assert_eq!(
+            format!("{:?}", Rfc3339(951_782_400, 0)),
+            "2000-02-29T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn test_into_raw() {
+        // Destroying this dangling pointer would crash, so this checks that
+        // dropping after `into_raw` does not destroy the iterator.
+        let ptr = NonNull::dangling().as_ptr();
+        let iter = unsafe { SubvolumeIdIterator::from_raw(ptr) };
+        assert_eq!(iter.into_raw(), ptr);
+
+        drop(unsafe { SubvolumeIdIterator::from_raw(std::ptr::null_mut()) });
+    }
+
+    #[test]
+    fn test_limit_exhausted() {
+        // Advancing this null iterator would crash, so this checks that the
+        // limit is checked first.
+        let mut iter = unsafe { SubvolumeIdIterator::from_raw(std::ptr::null_mut()) };
+        iter.remaining = Some(0);
+        assert!(iter.next().is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_limit_closes() {
+        let mut iter = unsafe { SubvolumeIdIterator::from_raw(std::ptr::null_mut()) };
+        iter.fd = Some(std::fs::File::open("/").unwrap().into());
+        iter.remaining = Some(2);
+        iter.count_item();
+        assert!(iter.fd.is_some());
+        iter.count_item();
+        assert!(iter.fd.is_none());
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn test_iterate_subvolume_send_sync() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+        let mut iter = IterateSubvolume::new("/");
+        iter.with_info_filter(|info| info.id() > 256);
+        assert_send_sync(&iter);
+    }
+}
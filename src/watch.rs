@@ -0,0 +1,83 @@
+use std::{collections::BTreeSet, ops::ControlFlow, path::Path, thread, time::Duration};
+
+use crate::{Error, IterateSubvolume};
+
+/// A change of the subvolumes of a filesystem, see [`watch_subvolumes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SubvolumeEvent {
+    /// The subvolume with this ID was created.
+    Created(u64),
+    /// The subvolume with this ID was deleted.
+    Deleted(u64),
+}
+
+/// Watches the subvolumes of the filesystem containing the `path`, calling
+/// `callback` for every subvolume created or deleted, until it returns
+/// [`ControlFlow::Break`] or listing the subvolumes fails.
+///
+/// Btrfs has no notification for subvolumes, so this lists the subvolume IDs
+/// every `interval` and reports the differences from the previous listing,
+/// deletions first, each in increasing order of ID. Subvolumes existing when
+/// called are not reported, nor are subvolumes created and deleted within
+/// one interval. Btrfs never reuses subvolume IDs, so an ID identifies one
+/// subvolume.
+///
+/// This blocks the calling thread and requires appropriate privilege
+/// (`CAP_SYS_ADMIN`).
+pub fn watch_subvolumes<P, F>(path: P, interval: Duration, mut callback: F) -> Result<(), Error>
+where
+    P: AsRef<Path>,
+    F: FnMut(SubvolumeEvent) -> ControlFlow<()>,
+{
+    let mut iter = IterateSubvolume::new(path);
+    iter.all();
+    let list = || -> Result<BTreeSet<u64>, Error> {
+        let mut ids = BTreeSet::new();
+        iter.for_each_id(|_, id| {
+            ids.insert(id);
+            ControlFlow::Continue(())
+        })?;
+        Ok(ids)
+    };
+
+    let mut ids = list()?;
+    loop {
+        thread::sleep(interval);
+        let new_ids = list()?;
+        for event in diff(&ids, &new_ids) {
+            if callback(event).is_break() {
+                return Ok(());
+            }
+        }
+        ids = new_ids;
+    }
+}
+
+/// Returns the events turning the `old` subvolume IDs into the `new` ones.
+fn diff(old: &BTreeSet<u64>, new: &BTreeSet<u64>) -> Vec<SubvolumeEvent> {
+    let deleted = old.difference(new).map(|&id| SubvolumeEvent::Deleted(id));
+    let created = new.difference(old).map(|&id| SubvolumeEvent::Created(id));
+    deleted.chain(created).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::{diff, SubvolumeEvent};
+
+    #[test]
+    fn test_diff() {
+        let old = BTreeSet::from([256, 257, 258]);
+        let new = BTreeSet::from([256, 258, 260, 259]);
+        assert_eq!(
+            diff(&old, &new),
+            [
+                SubvolumeEvent::Deleted(257),
+                SubvolumeEvent::Created(259),
+                SubvolumeEvent::Created(260),
+            ]
+        );
+        assert_eq!(diff(&new, &new), []);
+    }
+}
@@ -1,10 +1,15 @@
 mod common;
 
-use common::setup;
-use libbtrfsutil::{subvolume_info, subvolume_read_only};
+use common::{setup, CommandExt};
+use libbtrfsutil::{
+    subvolume_existence, subvolume_info, subvolume_read_only, Existence, QgroupInherit, Subvolume,
+    BTRFS_ROOT_SUBVOL_RDONLY,
+};
 use std::{
     num::NonZeroU64,
+    os::unix::fs::OpenOptionsExt,
     path::PathBuf,
+    process::Command,
     time::{Duration, SystemTime},
 };
 
@@ -117,3 +122,1489 @@ fn test_subvolume_path() {
     let ret_path = libbtrfsutil::subvolume_path(subvol_path).unwrap();
     assert_eq!(ret_path, PathBuf::from("subvol"));
 }
+
+#[test]
+fn test_subvolume_handle_read_only() {
+    let device = setup(
+        "test_subvolume_handle_read_only".into(),
+        "test_subvolume_handle_read_only_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let subvol = Subvolume::open(&subvol_path).unwrap();
+    assert!(!subvol.read_only().unwrap());
+    assert!(subvol.info_cached().is_none());
+    assert_eq!(subvol.id().unwrap(), 256);
+    let info = subvol.info_cached().unwrap();
+    assert_eq!(subvol.uuid().unwrap(), info.uuid());
+    assert_eq!(info.flags() & BTRFS_ROOT_SUBVOL_RDONLY, 0);
+
+    subvol.set_read_only(true).unwrap();
+    assert!(subvol.read_only().unwrap());
+    assert!(subvolume_read_only(&subvol_path).unwrap());
+    let cached = subvol.info_cached().unwrap();
+    assert_eq!(cached.flags(), subvol.info().unwrap().flags());
+    assert_ne!(cached.flags() & BTRFS_ROOT_SUBVOL_RDONLY, 0);
+
+    subvol.set_read_only(false).unwrap();
+    assert!(!subvol.read_only().unwrap());
+    assert_eq!(
+        subvol.info_cached().unwrap().flags() & BTRFS_ROOT_SUBVOL_RDONLY,
+        0
+    );
+}
+
+#[test]
+fn test_prepare_rollback() {
+    let device = setup(
+        "test_prepare_rollback".into(),
+        "test_prepare_rollback_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let snapshot_path = mountpoint.join("snapshot");
+    libbtrfsutil::CreateSnapshotOptions::new()
+        .readonly(true)
+        .create(&subvol_path, &snapshot_path)
+        .unwrap();
+
+    let rollback_path = mountpoint.join("rollback");
+    let plan = libbtrfsutil::prepare_rollback(&snapshot_path, &rollback_path).unwrap();
+    assert_eq!(plan.previous_default_id(), 5);
+    assert_eq!(
+        plan.id(),
+        libbtrfsutil::subvolume_id(&rollback_path).unwrap()
+    );
+    assert_eq!(
+        libbtrfsutil::default_subvolume(&mountpoint).unwrap(),
+        plan.id()
+    );
+    assert!(!subvolume_read_only(&rollback_path).unwrap());
+
+    plan.revert().unwrap();
+    assert_eq!(libbtrfsutil::default_subvolume(&mountpoint).unwrap(), 5);
+}
+
+#[test]
+fn test_subvolume_info_cache() {
+    let device = setup(
+        "test_subvolume_info_cache".into(),
+        "test_subvolume_info_cache_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let mut cache = libbtrfsutil::SubvolumeInfoCache::new(Duration::from_secs(60));
+    let info = cache.get(&mountpoint, 256).unwrap();
+    assert_eq!(info.flags() & BTRFS_ROOT_SUBVOL_RDONLY, 0);
+
+    libbtrfsutil::set_subvolume_read_only(&subvol_path, true).unwrap();
+    let cached = cache.get(&mountpoint, 256).unwrap();
+    assert_eq!(cached.flags() & BTRFS_ROOT_SUBVOL_RDONLY, 0);
+
+    cache.invalidate(256);
+    let refreshed = cache.get(&mountpoint, 256).unwrap();
+    assert_ne!(refreshed.flags() & BTRFS_ROOT_SUBVOL_RDONLY, 0);
+    assert_eq!(refreshed.uuid(), info.uuid());
+}
+
+#[test]
+fn test_subvolume_existence() {
+    let device = setup(
+        "test_subvolume_existence".into(),
+        "test_subvolume_existence_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let dir_path = mountpoint.join("dir");
+    std::fs::create_dir(&dir_path).unwrap();
+
+    assert_eq!(
+        subvolume_existence(&subvol_path).unwrap(),
+        Existence::Subvolume
+    );
+    assert_eq!(
+        subvolume_existence(&dir_path).unwrap(),
+        Existence::Directory
+    );
+    assert_eq!(
+        subvolume_existence(mountpoint.join("missing")).unwrap(),
+        Existence::Missing
+    );
+}
+
+#[test]
+fn test_create_subvolume_qgroup_inherit() {
+    let device = setup(
+        "test_create_subvolume_qgroup_inherit".into(),
+        "test_create_subvolume_qgroup_inherit_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    Command::new("btrfs")
+        .args(["quota", "enable"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+    Command::new("btrfs")
+        .args(["qgroup", "create", "1/100"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+
+    let mut qgroup = QgroupInherit::new().unwrap();
+    qgroup.add_group((1 << 48) | 100).unwrap();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::CreateSubvolumeOptions::new()
+        .qgroup(Some(qgroup))
+        .create(&subvol_path)
+        .unwrap();
+    let id = libbtrfsutil::subvolume_id(&subvol_path).unwrap();
+
+    let output = Command::new("btrfs")
+        .args(["qgroup", "show", "-p", "--raw"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+    let qgroupid = format!("0/{}", id);
+    let line = output
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some(qgroupid.as_str()))
+        .unwrap();
+    assert!(line.split_whitespace().any(|column| column == "1/100"));
+}
+
+#[test]
+fn test_quotas_enabled() {
+    let device = setup(
+        "test_quotas_enabled".into(),
+        "test_quotas_enabled_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    assert!(!libbtrfsutil::quotas_enabled(&mountpoint).unwrap());
+
+    Command::new("btrfs")
+        .args(["quota", "enable"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+    assert!(libbtrfsutil::quotas_enabled(&mountpoint).unwrap());
+
+    Command::new("btrfs")
+        .args(["quota", "disable"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+    assert!(!libbtrfsutil::quotas_enabled(&mountpoint).unwrap());
+}
+
+#[test]
+fn test_snapshot_qgroup_limit() {
+    let device = setup(
+        "test_snapshot_qgroup_limit".into(),
+        "test_snapshot_qgroup_limit_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let err = libbtrfsutil::set_qgroup_limit(&subvol_path, 1 << 20).unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::QGROUP_LIMIT_FAILED);
+    assert_eq!(err.errno(), libc::ENOTCONN);
+
+    Command::new("btrfs")
+        .args(["quota", "enable"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+    let snapshot_path = mountpoint.join("snapshot");
+    libbtrfsutil::CreateSnapshotOptions::new()
+        .with_qgroup_limit(1 << 20)
+        .create(&subvol_path, &snapshot_path)
+        .unwrap();
+    let id = libbtrfsutil::subvolume_id(&snapshot_path).unwrap();
+
+    let output = Command::new("btrfs")
+        .args(["qgroup", "show", "-r", "--raw"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+    let qgroupid = format!("0/{}", id);
+    let line = output
+        .lines()
+        .find(|line| line.split_whitespace().next() == Some(qgroupid.as_str()))
+        .unwrap();
+    assert!(line.split_whitespace().any(|column| column == "1048576"));
+}
+
+#[test]
+fn test_open_subvolume_symlink() {
+    let device = setup(
+        "test_open_subvolume_symlink".into(),
+        "test_open_subvolume_symlink_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let link_path = mountpoint.join("link");
+    std::os::unix::fs::symlink(&subvol_path, &link_path).unwrap();
+
+    let err = Subvolume::open(&link_path).err().unwrap();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::OPEN_FAILED);
+    libbtrfsutil::OpenSubvolumeOptions::new()
+        .follow_symlinks(true)
+        .open(&link_path)
+        .unwrap();
+}
+
+#[test]
+fn test_is_subvolume_mounted() {
+    let device = setup(
+        "test_is_subvolume_mounted".into(),
+        "test_is_subvolume_mounted_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    assert!(libbtrfsutil::is_subvolume_mounted(&mountpoint, 5).unwrap());
+    assert!(!libbtrfsutil::is_subvolume_mounted(&mountpoint, 256).unwrap());
+
+    let subvol_mountpoint = PathBuf::from("test_is_subvolume_mounted_subvol_dir");
+    std::fs::create_dir_all(&subvol_mountpoint).unwrap();
+    Command::new("mount")
+        .args(["-o", "subvolid=256", device.name()])
+        .arg(&subvol_mountpoint)
+        .call()
+        .unwrap();
+    let mounted = libbtrfsutil::is_subvolume_mounted(&mountpoint, 256);
+    Command::new("umount")
+        .arg(&subvol_mountpoint)
+        .call()
+        .unwrap();
+    std::fs::remove_dir(&subvol_mountpoint).unwrap();
+    assert!(mounted.unwrap());
+}
+
+#[test]
+fn test_iter_creation_error() {
+    let mut iter = libbtrfsutil::IterateSubvolume::new("/proc").iter();
+    let err = iter.next().unwrap().unwrap_err();
+    assert_eq!(err.context(), Some(std::path::Path::new("/proc")));
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test_subvolume_iter_children() {
+    let device = setup(
+        "test_subvolume_iter_children".into(),
+        "test_subvolume_iter_children_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    libbtrfsutil::create_subvolume(subvol_path.join("child")).unwrap();
+
+    let iter = Subvolume::open(&subvol_path)
+        .unwrap()
+        .iter_children()
+        .unwrap();
+    let children: Vec<PathBuf> = iter.map(|r| r.unwrap().0).collect();
+    assert_eq!(children, [PathBuf::from("child")]);
+}
+
+#[test]
+fn test_delete_protected_subvolume() {
+    let device = setup(
+        "test_delete_protected_subvolume".into(),
+        "test_delete_protected_subvolume_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let child_path = subvol_path.join("child");
+    libbtrfsutil::create_subvolume(&child_path).unwrap();
+
+    let mut protected = libbtrfsutil::ProtectedSet::new();
+    protected.protect(subvolume_info(&child_path).unwrap().uuid());
+
+    let err = libbtrfsutil::DeleteSubvolumeOptions::new()
+        .respect_protection(&protected)
+        .delete(&child_path)
+        .unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::PROTECTED);
+    let err = libbtrfsutil::DeleteSubvolumeOptions::new()
+        .recursive(true)
+        .respect_protection(&protected)
+        .delete(&subvol_path)
+        .unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::PROTECTED);
+    assert!(child_path.exists());
+
+    protected.unprotect(subvolume_info(&child_path).unwrap().uuid());
+    libbtrfsutil::DeleteSubvolumeOptions::new()
+        .recursive(true)
+        .respect_protection(&protected)
+        .delete(&subvol_path)
+        .unwrap();
+    assert!(!subvol_path.exists());
+}
+
+#[test]
+fn test_iter_root_relative_paths() {
+    let device = setup(
+        "test_iter_root_relative_paths".into(),
+        "test_iter_root_relative_paths_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    libbtrfsutil::create_subvolume(subvol_path.join("child")).unwrap();
+
+    let paths: Vec<PathBuf> = libbtrfsutil::IterateSubvolume::new(&subvol_path)
+        .root_relative_paths()
+        .iter_with_id()
+        .unwrap()
+        .map(|r| r.unwrap().0)
+        .collect();
+    assert_eq!(paths, [PathBuf::from("subvol/child")]);
+}
+
+#[test]
+fn test_create_readonly_subvolume() {
+    let device = setup(
+        "test_create_readonly_subvolume".into(),
+        "test_create_readonly_subvolume_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::CreateSubvolumeOptions::new()
+        .readonly(true)
+        .create(&subvol_path)
+        .unwrap();
+    assert!(subvolume_read_only(&subvol_path).unwrap());
+}
+
+#[test]
+fn test_classify_subvolumes() {
+    let device = setup(
+        "test_classify_subvolumes".into(),
+        "test_classify_subvolumes_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    libbtrfsutil::CreateSnapshotOptions::new()
+        .create(&subvol_path, mountpoint.join("snapshot"))
+        .unwrap();
+
+    let (originals, snapshots) = libbtrfsutil::classify_subvolumes(&mountpoint).unwrap();
+    assert_eq!(originals.len(), 1);
+    assert_eq!(snapshots.len(), 1);
+    assert!(snapshots[0].is_snapshot_of(&originals[0]));
+}
+
+#[test]
+fn test_filesystem_subvolume_by_path() {
+    let device = setup(
+        "test_filesystem_subvolume_by_path".into(),
+        "test_filesystem_subvolume_by_path_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    libbtrfsutil::create_subvolume(subvol_path.join("child")).unwrap();
+    std::fs::create_dir(subvol_path.join("dir")).unwrap();
+
+    let fs = libbtrfsutil::Filesystem::open(&mountpoint).unwrap();
+    assert_eq!(fs.subvolume_by_path("").unwrap().unwrap().id(), 5);
+    assert_eq!(fs.subvolume_by_path("/").unwrap().unwrap().id(), 5);
+    let child = fs.subvolume_by_path("subvol/child").unwrap().unwrap();
+    assert_eq!(
+        child.id(),
+        subvolume_info(subvol_path.join("child")).unwrap().id()
+    );
+    assert!(fs.subvolume_by_path("subvol/dir").unwrap().is_none());
+}
+
+#[test]
+fn test_delete_symlink() {
+    let device = setup(
+        "test_delete_symlink".into(),
+        "test_delete_symlink_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let link_path = mountpoint.join("link");
+    std::os::unix::fs::symlink(&subvol_path, &link_path).unwrap();
+
+    let err = libbtrfsutil::DeleteSubvolumeOptions::new()
+        .recursive(true)
+        .delete(&link_path)
+        .unwrap_err();
+    assert_eq!(err.errno(), libc::ELOOP);
+    assert!(subvol_path.exists());
+}
+
+#[test]
+fn test_iter_post_order() {
+    let device = setup(
+        "test_iter_post_order".into(),
+        "test_iter_post_order_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    libbtrfsutil::create_subvolume(subvol_path.join("a")).unwrap();
+    libbtrfsutil::create_subvolume(subvol_path.join("a/b")).unwrap();
+    libbtrfsutil::create_subvolume(subvol_path.join("a/b/c")).unwrap();
+    libbtrfsutil::create_subvolume(subvol_path.join("d")).unwrap();
+
+    let paths: Vec<PathBuf> = libbtrfsutil::IterateSubvolume::new(&subvol_path)
+        .post_order()
+        .iter_with_id()
+        .unwrap()
+        .map(|r| r.unwrap().0)
+        .collect();
+    assert_eq!(paths.len(), 4);
+    for (i, path) in paths.iter().enumerate() {
+        assert!(paths[..i].iter().all(|earlier| !path.starts_with(earlier)));
+    }
+
+    for path in &paths {
+        libbtrfsutil::delete_subvolume(subvol_path.join(path)).unwrap();
+    }
+    libbtrfsutil::delete_subvolume(&subvol_path).unwrap();
+}
+
+#[test]
+fn test_snapshot_match_source_read_only() {
+    let device = setup(
+        "test_snapshot_match_source_read_only".into(),
+        "test_snapshot_match_source_read_only_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let snapshot_path = mountpoint.join("snapshot");
+    libbtrfsutil::CreateSnapshotOptions::new()
+        .readonly(true)
+        .match_source_read_only(true)
+        .create(&subvol_path, &snapshot_path)
+        .unwrap();
+    assert!(!subvolume_read_only(&snapshot_path).unwrap());
+
+    libbtrfsutil::set_subvolume_read_only(&subvol_path, true).unwrap();
+    let snapshot_path = mountpoint.join("snapshot_ro");
+    libbtrfsutil::CreateSnapshotOptions::new()
+        .match_source_read_only(true)
+        .create(&subvol_path, &snapshot_path)
+        .unwrap();
+    assert!(subvolume_read_only(&snapshot_path).unwrap());
+}
+
+#[test]
+fn test_filesystem_from_fd() {
+    let device = setup(
+        "test_filesystem_from_fd".into(),
+        "test_filesystem_from_fd_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("subvol")).unwrap();
+
+    let file = std::fs::File::open(&mountpoint).unwrap();
+    let fs = libbtrfsutil::Filesystem::from_fd(file).unwrap();
+    assert!(fs.subvolume_by_path("subvol").unwrap().is_some());
+
+    let file = std::fs::File::open("/proc").unwrap();
+    let err = libbtrfsutil::Filesystem::from_fd(file).err().unwrap();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::NOT_BTRFS);
+}
+
+#[test]
+fn test_filesystem_deleted_subvolumes() {
+    let device = setup(
+        "test_filesystem_deleted_subvolumes".into(),
+        "test_filesystem_deleted_subvolumes_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let fs = libbtrfsutil::Filesystem::open(&mountpoint).unwrap();
+    assert_eq!(fs.deleted_subvolumes().unwrap(), []);
+
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let id = libbtrfsutil::subvolume_id(&subvol_path).unwrap();
+    libbtrfsutil::delete_subvolume(&subvol_path).unwrap();
+    assert_eq!(fs.deleted_subvolumes().unwrap(), [id]);
+
+    fs.sync().unwrap();
+}
+
+#[test]
+fn test_filesystem_subvolumes_with_usage() {
+    let device = setup(
+        "test_filesystem_subvolumes_with_usage".into(),
+        "test_filesystem_subvolumes_with_usage_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let fs = libbtrfsutil::Filesystem::open(&mountpoint).unwrap();
+
+    let subvols = fs.subvolumes_with_usage().unwrap();
+    let ids: Vec<u64> = subvols.iter().map(|(info, _)| info.id()).collect();
+    assert_eq!(ids, [5, 256]);
+    assert!(subvols.iter().all(|(_, usage)| usage.is_none()));
+
+    Command::new("btrfs")
+        .args(["quota", "enable"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+    std::fs::write(subvol_path.join("file"), vec![1u8; 1 << 20]).unwrap();
+    libbtrfsutil::sync(&mountpoint).unwrap();
+    Command::new("btrfs")
+        .args(["quota", "rescan", "-w"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+
+    let subvols = fs.subvolumes_with_usage().unwrap();
+    let (_, usage) = subvols.iter().find(|(info, _)| info.id() == 256).unwrap();
+    let usage = usage.unwrap();
+    assert!(usage.referenced >= 1 << 20);
+    assert!(usage.exclusive >= 1 << 20);
+}
+
+#[test]
+fn test_filesystem_from_fd_o_path() {
+    let device = setup(
+        "test_filesystem_from_fd_o_path".into(),
+        "test_filesystem_from_fd_o_path_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("subvol")).unwrap();
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+        .open(&mountpoint)
+        .unwrap();
+    let fs = libbtrfsutil::Filesystem::from_fd(file).unwrap();
+    assert!(fs.subvolume_by_path("subvol").unwrap().is_some());
+}
+
+#[test]
+fn test_fd_o_path() {
+    let device = setup("test_fd_o_path".into(), "test_fd_o_path_dir".into());
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let id = libbtrfsutil::subvolume_id(&subvol_path).unwrap();
+
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_NOFOLLOW)
+        .open(&subvol_path)
+        .unwrap();
+    assert!(libbtrfsutil::is_subvolume_fd(&file).unwrap());
+    assert_eq!(libbtrfsutil::subvolume_id_fd(&file).unwrap(), id);
+    let info = libbtrfsutil::subvolume_info_fd(&file, 0).unwrap();
+    assert_eq!(info.id(), id);
+    assert_eq!(info.uuid(), subvolume_info(&subvol_path).unwrap().uuid());
+    assert_eq!(
+        libbtrfsutil::subvolume_path_fd(&file, 0).unwrap(),
+        PathBuf::from("subvol")
+    );
+}
+
+#[test]
+fn test_read_only_map() {
+    let device = setup("test_read_only_map".into(), "test_read_only_map_dir".into());
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let snapshot_path = mountpoint.join("snapshot");
+    libbtrfsutil::CreateSnapshotOptions::new()
+        .readonly(true)
+        .create(&subvol_path, &snapshot_path)
+        .unwrap();
+
+    let map = libbtrfsutil::read_only_map(&mountpoint).unwrap();
+    assert_eq!(map.len(), 2);
+    assert!(!map[&libbtrfsutil::subvolume_id(&subvol_path).unwrap()]);
+    assert!(map[&libbtrfsutil::subvolume_id(&snapshot_path).unwrap()]);
+}
+
+#[test]
+fn test_subvolume_basic_info() {
+    let device = setup(
+        "test_subvolume_basic_info".into(),
+        "test_subvolume_basic_info_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let info = subvolume_info(&subvol_path).unwrap();
+    let basic = libbtrfsutil::subvolume_basic_info(&subvol_path).unwrap();
+    assert_eq!(basic.id, info.id());
+    assert_eq!(basic.generation, Some(info.generation()));
+}
+
+#[test]
+fn test_subvolume_path_with_id_context() {
+    let device = setup(
+        "test_subvolume_path_with_id_context".into(),
+        "test_subvolume_path_with_id_context_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+
+    let err = libbtrfsutil::subvolume_path_with_id(&mountpoint, 9999).unwrap_err();
+    assert_eq!(err.subvolume_id(), Some(9999));
+    assert_eq!(err.context(), Some(mountpoint.as_path()));
+}
+
+#[test]
+fn test_iter_unprivileged() {
+    let device = setup(
+        "test_iter_unprivileged".into(),
+        "test_iter_unprivileged_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    libbtrfsutil::create_subvolume(subvol_path.join("child")).unwrap();
+
+    // The raw syscalls only change the credentials of the calling thread.
+    let paths = std::thread::spawn(move || {
+        unsafe {
+            assert_eq!(libc::syscall(libc::SYS_setresgid, 65534, 65534, 65534), 0);
+            assert_eq!(libc::syscall(libc::SYS_setresuid, 65534, 65534, 65534), 0);
+        }
+        libbtrfsutil::IterateSubvolume::new(&subvol_path)
+            .iter_with_id()
+            .unwrap()
+            .map(|r| r.unwrap().0)
+            .collect::<Vec<PathBuf>>()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(paths, [PathBuf::from("child")]);
+}
+
+#[test]
+fn test_subvolume_handle_exists() {
+    let device = setup(
+        "test_subvolume_handle_exists".into(),
+        "test_subvolume_handle_exists_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let subvol = Subvolume::open(&subvol_path).unwrap();
+    assert!(subvol.exists());
+    libbtrfsutil::delete_subvolume(&subvol_path).unwrap();
+    assert!(!subvol.exists());
+}
+
+#[test]
+fn test_iter_with_prefix() {
+    let device = setup(
+        "test_iter_with_prefix".into(),
+        "test_iter_with_prefix_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("snapshots")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("snapshots/daily-1")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("snapshotsx")).unwrap();
+
+    let paths: Vec<PathBuf> = libbtrfsutil::IterateSubvolume::new(&mountpoint)
+        .with_prefix("snapshots")
+        .iter_with_info()
+        .unwrap()
+        .map(|r| r.unwrap().0)
+        .collect();
+    assert_eq!(
+        paths,
+        [
+            PathBuf::from("snapshots"),
+            PathBuf::from("snapshots/daily-1")
+        ]
+    );
+}
+
+#[test]
+fn test_create_subvolume_owner() {
+    use std::os::unix::fs::MetadataExt;
+
+    let device = setup(
+        "test_create_subvolume_owner".into(),
+        "test_create_subvolume_owner_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::CreateSubvolumeOptions::new()
+        .owner(1000, 1001)
+        .readonly(true)
+        .create(&subvol_path)
+        .unwrap();
+    let metadata = std::fs::metadata(&subvol_path).unwrap();
+    assert_eq!(metadata.uid(), 1000);
+    assert_eq!(metadata.gid(), 1001);
+    assert!(subvolume_read_only(&subvol_path).unwrap());
+}
+
+#[test]
+fn test_subvolume_handle_ready_to_send() {
+    let device = setup(
+        "test_subvolume_handle_ready_to_send".into(),
+        "test_subvolume_handle_ready_to_send_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let subvol = Subvolume::open(&subvol_path).unwrap();
+    let err = subvol.ready_to_send().unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::NOT_READ_ONLY);
+    subvol.set_read_only(true).unwrap();
+    subvol.ready_to_send().unwrap();
+}
+
+#[test]
+fn test_filesystem_label() {
+    let device = setup(
+        "test_filesystem_label".into(),
+        "test_filesystem_label_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+
+    let fs = libbtrfsutil::Filesystem::open(&mountpoint).unwrap();
+    assert_eq!(fs.label().unwrap(), "");
+    Command::new("btrfs")
+        .args(["filesystem", "label"])
+        .arg(&mountpoint)
+        .arg("data")
+        .call()
+        .unwrap();
+    assert_eq!(fs.label().unwrap(), "data");
+}
+
+#[test]
+fn test_mountpoint_of() {
+    let device = setup("test_mountpoint_of".into(), "test_mountpoint_of_dir".into());
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("subvol")).unwrap();
+
+    assert_eq!(
+        libbtrfsutil::mountpoint_of(&mountpoint, 5).unwrap(),
+        Some(std::fs::canonicalize(&mountpoint).unwrap())
+    );
+    assert_eq!(libbtrfsutil::mountpoint_of(&mountpoint, 256).unwrap(), None);
+}
+
+#[test]
+fn test_snapshot_cross_filesystem() {
+    let device = setup(
+        "test_snapshot_cross_filesystem".into(),
+        "test_snapshot_cross_filesystem_dir".into(),
+    );
+    let other = setup(
+        "test_snapshot_cross_filesystem_other".into(),
+        "test_snapshot_cross_filesystem_other_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let err = libbtrfsutil::CreateSnapshotOptions::new()
+        .create(&subvol_path, other.mountpoint().unwrap().join("snapshot"))
+        .unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::CROSS_FILESYSTEM);
+    assert_eq!(err.errno(), libc::EXDEV);
+}
+
+#[test]
+fn test_iter_for_each_id() {
+    let device = setup(
+        "test_iter_for_each_id".into(),
+        "test_iter_for_each_id_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("a")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("b")).unwrap();
+
+    let mut seen = Vec::new();
+    libbtrfsutil::IterateSubvolume::new(&mountpoint)
+        .for_each_id(|path, id| {
+            seen.push((path.to_str().unwrap().to_owned(), id));
+            std::ops::ControlFlow::Break(())
+        })
+        .unwrap();
+    assert_eq!(seen, [("a".to_owned(), 256)]);
+}
+
+#[test]
+fn test_set_default_subvolume_to_path() {
+    let device = setup(
+        "test_set_default_subvolume_to_path".into(),
+        "test_set_default_subvolume_to_path_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    std::fs::create_dir(mountpoint.join("dir")).unwrap();
+
+    let err = libbtrfsutil::set_default_subvolume_to_path(&mountpoint, mountpoint.join("dir"))
+        .unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::NOT_SUBVOLUME);
+
+    libbtrfsutil::set_default_subvolume_to_path(&mountpoint, &subvol_path).unwrap();
+    assert_eq!(
+        libbtrfsutil::default_subvolume(&mountpoint).unwrap(),
+        libbtrfsutil::subvolume_id(&subvol_path).unwrap()
+    );
+}
+
+#[test]
+fn test_create_subvolume_idempotent() {
+    let device = setup(
+        "test_create_subvolume_idempotent".into(),
+        "test_create_subvolume_idempotent_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    assert!(libbtrfsutil::create_subvolume(&subvol_path).is_err());
+    libbtrfsutil::CreateSubvolumeOptions::new()
+        .idempotent(true)
+        .create(&subvol_path)
+        .unwrap();
+
+    let dir_path = mountpoint.join("dir");
+    std::fs::create_dir(&dir_path).unwrap();
+    let err = libbtrfsutil::CreateSubvolumeOptions::new()
+        .idempotent(true)
+        .create(&dir_path)
+        .unwrap_err();
+    assert_eq!(err.errno(), libc::EEXIST);
+}
+
+#[test]
+fn test_set_subvolume_read_only_durable() {
+    let device = setup(
+        "test_set_subvolume_read_only_durable".into(),
+        "test_set_subvolume_read_only_durable_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    libbtrfsutil::set_subvolume_read_only_durable(&subvol_path, true).unwrap();
+    assert!(subvolume_read_only(&subvol_path).unwrap());
+}
+
+#[test]
+fn test_delete_plan() {
+    let device = setup("test_delete_plan".into(), "test_delete_plan_dir".into());
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    libbtrfsutil::create_subvolume(subvol_path.join("child")).unwrap();
+
+    let plan = libbtrfsutil::DeleteSubvolumeOptions::new()
+        .recursive(true)
+        .plan(&subvol_path)
+        .unwrap();
+    let paths: Vec<&PathBuf> = plan.subvolumes().iter().map(|(path, _)| path).collect();
+    assert_eq!(paths, [&subvol_path.join("child"), &subvol_path]);
+    assert!(subvol_path.join("child").exists());
+
+    let plan = libbtrfsutil::DeleteSubvolumeOptions::new()
+        .plan(&subvol_path)
+        .unwrap();
+    assert_eq!(plan.subvolumes().len(), 1);
+}
+
+#[test]
+fn test_iter_subvolumes_under() {
+    let device = setup(
+        "test_iter_subvolumes_under".into(),
+        "test_iter_subvolumes_under_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let a_path = mountpoint.join("a");
+    libbtrfsutil::create_subvolume(&a_path).unwrap();
+    libbtrfsutil::create_subvolume(a_path.join("b")).unwrap();
+    libbtrfsutil::create_subvolume(a_path.join("b/c")).unwrap();
+
+    let a = libbtrfsutil::subvolume_id(&a_path).unwrap();
+    let b = libbtrfsutil::subvolume_id(a_path.join("b")).unwrap();
+    let paths: Vec<PathBuf> = libbtrfsutil::iter_subvolumes_under(&mountpoint, &[b, a])
+        .unwrap()
+        .map(|r| r.unwrap().0)
+        .collect();
+    assert_eq!(paths, [PathBuf::from("a/b/c"), PathBuf::from("a/b")]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_subvolume_info_json() {
+    let device = setup(
+        "test_subvolume_info_json".into(),
+        "test_subvolume_info_json_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let json = Subvolume::open(&subvol_path).unwrap().info_json().unwrap();
+    assert!(json.starts_with("{\"id\":256,\"parent_id\":5,"));
+    assert!(json.contains("\"stransid\":null"));
+}
+
+#[test]
+fn test_walk_subvolumes() {
+    let device = setup(
+        "test_walk_subvolumes".into(),
+        "test_walk_subvolumes_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("a")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("a/b")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("a/b/c")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("a/d")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("e")).unwrap();
+
+    let mut visited = Vec::new();
+    libbtrfsutil::walk_subvolumes(&mountpoint, |path, info, parent| {
+        if let Some(parent) = parent {
+            assert_eq!(info.parent_id(), NonZeroU64::new(parent.id()));
+        }
+        visited.push((path.to_path_buf(), parent.is_some()));
+    })
+    .unwrap();
+    visited.sort();
+    assert_eq!(
+        visited,
+        [
+            (PathBuf::from("a"), false),
+            (PathBuf::from("a/b"), true),
+            (PathBuf::from("a/b/c"), true),
+            (PathBuf::from("a/d"), true),
+            (PathBuf::from("e"), false),
+        ]
+    );
+}
+
+#[test]
+fn test_read_only_fs_error() {
+    let device = setup(
+        "test_read_only_fs_error".into(),
+        "test_read_only_fs_error_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("subvol")).unwrap();
+    Command::new("mount")
+        .args(["-o", "remount,ro"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+
+    let create_err = libbtrfsutil::create_subvolume(mountpoint.join("new")).unwrap_err();
+    let delete_err = libbtrfsutil::delete_subvolume(mountpoint.join("subvol")).unwrap_err();
+    Command::new("mount")
+        .args(["-o", "remount,rw"])
+        .arg(&mountpoint)
+        .call()
+        .unwrap();
+    assert!(create_err.is_read_only_fs());
+    assert!(delete_err.is_read_only_fs());
+}
+
+#[test]
+fn test_create_snapshot_fd_at() {
+    let device = setup(
+        "test_create_snapshot_fd_at".into(),
+        "test_create_snapshot_fd_at_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    std::fs::create_dir(mountpoint.join("snapshots")).unwrap();
+
+    let source = std::fs::File::open(&subvol_path).unwrap();
+    let dir = std::fs::File::open(mountpoint.join("snapshots")).unwrap();
+    let mut options = libbtrfsutil::CreateSnapshotOptions::new();
+    options.readonly(true);
+    for name in ["", ".", "..", "a/b"] {
+        let err = options
+            .create_fd_at(&source, &dir, name.as_ref())
+            .unwrap_err();
+        assert_eq!(err.kind(), libbtrfsutil::ErrorKind::INVALID_ARGUMENT);
+    }
+    options
+        .create_fd_at(&source, &dir, "snap".as_ref())
+        .unwrap();
+    assert!(subvolume_read_only(mountpoint.join("snapshots/snap")).unwrap());
+}
+
+#[test]
+fn test_filesystem_subvolume_count() {
+    let device = setup(
+        "test_filesystem_subvolume_count".into(),
+        "test_filesystem_subvolume_count_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+
+    let fs = libbtrfsutil::Filesystem::open(&mountpoint).unwrap();
+    assert_eq!(fs.subvolume_count().unwrap(), 0);
+    libbtrfsutil::create_subvolume(mountpoint.join("a")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("a/b")).unwrap();
+    assert_eq!(fs.subvolume_count().unwrap(), 2);
+}
+
+#[test]
+fn test_info_is_read_only() {
+    let device = setup(
+        "test_info_is_read_only".into(),
+        "test_info_is_read_only_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    let snapshot_path = mountpoint.join("snapshot");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    libbtrfsutil::CreateSnapshotOptions::new()
+        .readonly(true)
+        .create(&subvol_path, &snapshot_path)
+        .unwrap();
+
+    for path in [&subvol_path, &snapshot_path] {
+        assert_eq!(
+            subvolume_info(path).unwrap().is_read_only(),
+            subvolume_read_only(path).unwrap()
+        );
+    }
+    assert!(subvolume_info(&snapshot_path).unwrap().is_read_only());
+
+    libbtrfsutil::set_subvolume_read_only(&snapshot_path, false).unwrap();
+    assert!(!subvolume_info(&snapshot_path).unwrap().is_read_only());
+    assert!(!subvolume_read_only(&snapshot_path).unwrap());
+}
+
+#[test]
+fn test_iter_with_info_filter() {
+    let device = setup(
+        "test_iter_with_info_filter".into(),
+        "test_iter_with_info_filter_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("a")).unwrap();
+    libbtrfsutil::CreateSnapshotOptions::new()
+        .readonly(true)
+        .create(mountpoint.join("a"), mountpoint.join("b"))
+        .unwrap();
+
+    let paths: Vec<PathBuf> = libbtrfsutil::IterateSubvolume::new(&mountpoint)
+        .with_info_filter(|info| info.is_read_only())
+        .iter_with_info()
+        .unwrap()
+        .map(|r| r.unwrap().0)
+        .collect();
+    assert_eq!(paths, [PathBuf::from("b")]);
+}
+
+#[test]
+fn test_subvolume_path_by_uuid() {
+    let device = setup(
+        "test_subvolume_path_by_uuid".into(),
+        "test_subvolume_path_by_uuid_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("a")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("a/b")).unwrap();
+    let uuid = subvolume_info(mountpoint.join("a/b")).unwrap().uuid();
+
+    assert_eq!(
+        libbtrfsutil::subvolume_path_by_uuid(&mountpoint, uuid).unwrap(),
+        Some(PathBuf::from("a/b"))
+    );
+    std::fs::rename(mountpoint.join("a/b"), mountpoint.join("c")).unwrap();
+    assert_eq!(
+        libbtrfsutil::subvolume_path_by_uuid(&mountpoint, uuid).unwrap(),
+        Some(PathBuf::from("c"))
+    );
+    assert_eq!(
+        libbtrfsutil::subvolume_path_by_uuid(&mountpoint, uuid::Uuid::nil()).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_filesystem_subvolume() {
+    let device = setup(
+        "test_filesystem_subvolume".into(),
+        "test_filesystem_subvolume_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("a")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("a/b")).unwrap();
+    let id = libbtrfsutil::subvolume_id(mountpoint.join("a/b")).unwrap();
+
+    let fs = libbtrfsutil::Filesystem::open(&mountpoint).unwrap();
+    assert_eq!(fs.subvolume(id).unwrap().id().unwrap(), id);
+    assert_eq!(
+        fs.subvolume(5).unwrap().id().unwrap(),
+        libbtrfsutil::FS_TREE_OBJECTID
+    );
+    let err = fs.subvolume(1000).err().unwrap();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::SUBVOLUME_NOT_FOUND);
+}
+
+#[test]
+fn test_subvolumes_parallel() {
+    let device = setup(
+        "test_subvolumes_parallel".into(),
+        "test_subvolumes_parallel_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    for i in 0..10 {
+        libbtrfsutil::create_subvolume(mountpoint.join(format!("subvol{}", i))).unwrap();
+    }
+
+    let result = libbtrfsutil::subvolumes_parallel(&mountpoint, 4).unwrap();
+    assert!(result.errors.is_empty());
+    let mut subvols = result.subvolumes;
+    subvols.sort_by_key(|(_, info)| info.id());
+    assert_eq!(subvols.len(), 10);
+    for (i, (path, info)) in subvols.iter().enumerate() {
+        assert_eq!(path, &PathBuf::from(format!("subvol{}", i)));
+        assert_eq!(info.id(), 256 + i as u64);
+    }
+
+    let result = libbtrfsutil::subvolumes_parallel(&mountpoint, 64).unwrap();
+    assert_eq!(result.subvolumes.len(), 10);
+}
+
+#[test]
+fn test_subvolume_info_file_path() {
+    let device = setup(
+        "test_subvolume_info_file_path".into(),
+        "test_subvolume_info_file_path_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    std::fs::create_dir(subvol_path.join("dir")).unwrap();
+    std::fs::write(subvol_path.join("dir/file"), b"data").unwrap();
+
+    let info = subvolume_info(&subvol_path).unwrap();
+    assert_eq!(subvolume_info(subvol_path.join("dir")).unwrap(), info);
+    assert_eq!(subvolume_info(subvol_path.join("dir/file")).unwrap(), info);
+    assert_eq!(
+        subvolume_info(subvol_path.join("dir/file")).unwrap().id(),
+        256
+    );
+}
+
+#[test]
+fn test_watch_subvolumes() {
+    let device = setup(
+        "test_watch_subvolumes".into(),
+        "test_watch_subvolumes_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(mountpoint.join("existing")).unwrap();
+
+    let creator = {
+        let subvol_path = subvol_path.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+            std::thread::sleep(Duration::from_millis(300));
+            libbtrfsutil::delete_subvolume(&subvol_path).unwrap();
+        })
+    };
+    let mut events = Vec::new();
+    libbtrfsutil::watch_subvolumes(&mountpoint, Duration::from_millis(50), |event| {
+        events.push(event);
+        if events.len() == 2 {
+            std::ops::ControlFlow::Break(())
+        } else {
+            std::ops::ControlFlow::Continue(())
+        }
+    })
+    .unwrap();
+    creator.join().unwrap();
+    assert_eq!(
+        events,
+        [
+            libbtrfsutil::SubvolumeEvent::Created(257),
+            libbtrfsutil::SubvolumeEvent::Deleted(257),
+        ]
+    );
+}
+
+#[test]
+fn test_is_btrfs() {
+    let device = setup("test_is_btrfs".into(), "test_is_btrfs_dir".into());
+    let mountpoint = device.mountpoint().unwrap().clone();
+
+    assert!(libbtrfsutil::is_btrfs(&mountpoint).unwrap());
+    assert!(!libbtrfsutil::is_btrfs("/proc").unwrap());
+    let err = libbtrfsutil::is_btrfs(mountpoint.join("missing")).unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::STATFS_FAILED);
+    assert_eq!(err.errno(), libc::ENOENT);
+}
+
+#[test]
+fn test_iter_flat_paths() {
+    let device = setup(
+        "test_iter_flat_paths".into(),
+        "test_iter_flat_paths_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    for path in ["b", "a", "a/c", "a-b", "a/c/d"] {
+        libbtrfsutil::create_subvolume(mountpoint.join(path)).unwrap();
+    }
+
+    let paths: Vec<PathBuf> = libbtrfsutil::IterateSubvolume::flat_paths(&mountpoint)
+        .unwrap()
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    let expected: Vec<PathBuf> = ["a", "a/c", "a/c/d", "a-b", "b"]
+        .iter()
+        .map(|path| mountpoint.join(path))
+        .collect();
+    assert_eq!(paths, expected);
+}
+
+#[test]
+fn test_subvolume_info_into() {
+    let device = setup(
+        "test_subvolume_info_into".into(),
+        "test_subvolume_info_into_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    libbtrfsutil::create_subvolume(mountpoint.join("a")).unwrap();
+    libbtrfsutil::create_subvolume(mountpoint.join("b")).unwrap();
+
+    let mut info = libbtrfsutil::SubvolumeInfo::new();
+    libbtrfsutil::subvolume_info_into(&mountpoint, 256, &mut info).unwrap();
+    assert_eq!(info, subvolume_info(mountpoint.join("a")).unwrap());
+    libbtrfsutil::subvolume_info_into(&mountpoint, 257, &mut info).unwrap();
+    assert_eq!(info, subvolume_info(mountpoint.join("b")).unwrap());
+
+    let err = libbtrfsutil::subvolume_info_into(&mountpoint, 1000, &mut info).unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::SUBVOLUME_NOT_FOUND);
+    assert_eq!(info.id(), 257);
+    assert_eq!(
+        info.uuid(),
+        subvolume_info(mountpoint.join("b")).unwrap().uuid()
+    );
+}
+
+#[test]
+fn test_subvolume_handle_received_info() {
+    let device = setup(
+        "test_subvolume_handle_received_info".into(),
+        "test_subvolume_handle_received_info_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    let subvol = Subvolume::open(&subvol_path).unwrap();
+    assert_eq!(subvol.received_info().unwrap(), None);
+    assert!(subvol.info_cached().is_some());
+}
+
+#[test]
+fn test_copy_received_metadata() {
+    let device = setup(
+        "test_copy_received_metadata".into(),
+        "test_copy_received_metadata_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let subvol_path = mountpoint.join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+    let snapshot_path = mountpoint.join("snapshot");
+    libbtrfsutil::CreateSnapshotOptions::new()
+        .readonly(true)
+        .create(&subvol_path, &snapshot_path)
+        .unwrap();
+
+    let stream_path = mountpoint.join("stream");
+    Command::new("btrfs")
+        .args(["send", "-f"])
+        .arg(&stream_path)
+        .arg(&snapshot_path)
+        .call()
+        .unwrap();
+    let received_dir = mountpoint.join("received");
+    std::fs::create_dir(&received_dir).unwrap();
+    Command::new("btrfs")
+        .args(["receive", "-f"])
+        .arg(&stream_path)
+        .arg(&received_dir)
+        .call()
+        .unwrap();
+    let received = subvolume_info(received_dir.join("snapshot")).unwrap();
+    assert!(received.received_uuid().is_some());
+
+    let err =
+        libbtrfsutil::copy_received_metadata(&subvolume_info(&subvol_path).unwrap(), &subvol_path)
+            .unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::INVALID_ARGUMENT);
+    let err = libbtrfsutil::copy_received_metadata(&received, &snapshot_path).unwrap_err();
+    assert_eq!(
+        err.kind(),
+        libbtrfsutil::ErrorKind::SET_RECEIVED_SUBVOL_FAILED
+    );
+    assert_eq!(err.errno(), libc::EROFS);
+
+    libbtrfsutil::copy_received_metadata(&received, &subvol_path).unwrap();
+    let info = subvolume_info(&subvol_path).unwrap();
+    assert_eq!(info.received_uuid(), received.received_uuid());
+    assert_eq!(info.stransid(), received.stransid());
+    assert!(info.rtransid().is_some());
+}
+
+#[test]
+fn test_iter_limit() {
+    let device = setup("test_iter_limit".into(), "test_iter_limit_dir".into());
+    let mountpoint = device.mountpoint().unwrap().clone();
+    for i in 0..5 {
+        libbtrfsutil::create_subvolume(mountpoint.join(format!("subvol{}", i))).unwrap();
+    }
+
+    let count = |limit| {
+        libbtrfsutil::IterateSubvolume::new(&mountpoint)
+            .limit(limit)
+            .iter_with_info()
+            .unwrap()
+            .map(Result::unwrap)
+            .count()
+    };
+    assert_eq!(count(0), 0);
+    assert_eq!(count(3), 3);
+    assert_eq!(count(10), 5);
+
+    let page = |min_id| -> Vec<u64> {
+        libbtrfsutil::IterateSubvolume::new(&mountpoint)
+            .min_id(min_id)
+            .limit(2)
+            .iter_with_id()
+            .unwrap()
+            .map(|item| item.unwrap().1.get())
+            .collect()
+    };
+    assert_eq!(page(0), [256, 257]);
+    assert_eq!(page(258), [258, 259]);
+    assert_eq!(page(260), [260]);
+    assert_eq!(page(261), []);
+}
+
+#[test]
+fn test_read_only_flags_round_trip() {
+    let device = setup(
+        "test_read_only_flags_round_trip".into(),
+        "test_read_only_flags_round_trip_dir".into(),
+    );
+    let subvol_path = device.mountpoint().unwrap().clone().join("subvol");
+    libbtrfsutil::create_subvolume(&subvol_path).unwrap();
+
+    libbtrfsutil::set_subvolume_read_only(&subvol_path, true).unwrap();
+    let info = subvolume_info(&subvol_path).unwrap();
+    assert_ne!(info.flags() & BTRFS_ROOT_SUBVOL_RDONLY, 0);
+    assert!(info.is_read_only());
+
+    libbtrfsutil::set_subvolume_read_only(&subvol_path, false).unwrap();
+    let info = subvolume_info(&subvol_path).unwrap();
+    assert_eq!(info.flags() & BTRFS_ROOT_SUBVOL_RDONLY, 0);
+    assert!(!info.is_read_only());
+}
+
+#[test]
+fn test_delete_subvolume_cancel() {
+    let device = setup(
+        "test_delete_subvolume_cancel".into(),
+        "test_delete_subvolume_cancel_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let a_path = mountpoint.join("a");
+    libbtrfsutil::create_subvolume(&a_path).unwrap();
+    libbtrfsutil::create_subvolume(a_path.join("b")).unwrap();
+
+    let cancel = std::sync::atomic::AtomicBool::new(true);
+    let err = libbtrfsutil::DeleteSubvolumeOptions::new()
+        .recursive(true)
+        .with_cancel(&cancel)
+        .delete(&a_path)
+        .unwrap_err();
+    assert_eq!(err.kind(), libbtrfsutil::ErrorKind::CANCELLED);
+    assert_eq!(err.context(), Some(a_path.join("b").as_path()));
+    assert_eq!(
+        subvolume_existence(a_path.join("b")).unwrap(),
+        Existence::Subvolume
+    );
+
+    let outcome = libbtrfsutil::DeleteSubvolumeOptions::new()
+        .recursive(true)
+        .with_cancel(&cancel)
+        .delete_with_outcome(&a_path)
+        .unwrap();
+    assert_eq!(
+        outcome,
+        libbtrfsutil::DeleteOutcome::Cancelled {
+            deleted: vec![],
+            remaining: vec![(a_path.join("b"), 257), (a_path.clone(), 256)],
+        }
+    );
+
+    cancel.store(false, std::sync::atomic::Ordering::Relaxed);
+    libbtrfsutil::DeleteSubvolumeOptions::new()
+        .recursive(true)
+        .with_cancel(&cancel)
+        .delete(&a_path)
+        .unwrap();
+    assert_eq!(subvolume_existence(&a_path).unwrap(), Existence::Missing);
+}
+
+#[test]
+fn test_delete_subvolume_cancel_midway() {
+    let device = setup(
+        "test_delete_subvolume_cancel_midway".into(),
+        "test_delete_subvolume_cancel_midway_dir".into(),
+    );
+    let mountpoint = device.mountpoint().unwrap().clone();
+    let a_path = mountpoint.join("a");
+    libbtrfsutil::create_subvolume(&a_path).unwrap();
+    let children: Vec<PathBuf> = (0..32).map(|i| a_path.join(format!("s{}", i))).collect();
+    for child in &children {
+        libbtrfsutil::create_subvolume(child).unwrap();
+    }
+
+    // Cancel as soon as the first child is observed deleted.
+    let cancel = std::sync::atomic::AtomicBool::new(false);
+    let outcome = std::thread::scope(|s| {
+        s.spawn(|| {
+            while children
+                .iter()
+                .all(|child| subvolume_existence(child).unwrap() == Existence::Subvolume)
+            {
+            }
+            cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+        libbtrfsutil::DeleteSubvolumeOptions::new()
+            .recursive(true)
+            .with_cancel(&cancel)
+            .delete_with_outcome(&a_path)
+            .unwrap()
+    });
+    let libbtrfsutil::DeleteOutcome::Cancelled { deleted, remaining } = outcome else {
+        panic!("deletion was not cancelled");
+    };
+    assert!(!deleted.is_empty());
+    for (path, _) in &deleted {
+        assert_eq!(subvolume_existence(path).unwrap(), Existence::Missing);
+    }
+    for (path, _) in &remaining {
+        assert_eq!(subvolume_existence(path).unwrap(), Existence::Subvolume);
+    }
+    assert_eq!(deleted.len() + remaining.len(), 33);
+    assert_eq!(remaining.last().unwrap().0, a_path);
+}